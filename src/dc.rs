@@ -0,0 +1,370 @@
+//! Compatibility with GNU `dc`: a parser for its syntax that produces the same values as our own parser,
+//! plus the few places where `dc` numbers behave differently, like how many fractional digits results get.
+use crate::{
+    error::Error,
+    num::{Mode, Num, Precision},
+    parser::string,
+    Result,
+    V::{self, *},
+};
+use bigdecimal::{num_bigint::BigInt, BigDecimal, RoundingMode, Signed, ToPrimitive, Zero};
+use nom::{
+    branch::alt,
+    bytes::complete::{take_till, take_while1},
+    character::complete::{anychar, char, multispace0, one_of},
+    combinator::{consumed, map, opt, recognize, value},
+    multi::many0,
+    sequence::preceded,
+    IResult, Parser,
+};
+use num_rational::BigRational;
+
+/// Which language the input is in and whose conventions the machine follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    Dcr,
+    Dc,
+}
+
+/// `dc` breaks numbers into lines of this length, including the backslash at the end.
+const LINE_LENGTH: usize = 70;
+
+const COMMANDS: &str = "+-*/%^v~pnPfcdrzkKiIoOxq";
+
+pub fn parse(input: &str) -> Result<Vec<V>> {
+    match parse_with_offsets(input) {
+        Ok(values) => Ok(values.into_iter().map(|(_, v)| v).collect()),
+        Err((_, e)) => Err(e),
+    }
+}
+
+/// Like `parse`, but with the byte offset of the command that each value comes from, also for errors.
+pub fn parse_with_offsets(input: &str) -> std::result::Result<Vec<(usize, V)>, (usize, Error)> {
+    let offset = |s: &str| s.as_ptr() as usize - input.as_ptr() as usize;
+    match many0(preceded(multispace0, consumed(command))).parse(input) {
+        Ok((rest, commands)) => match rest.trim_start().chars().next() {
+            None => Ok(commands
+                .into_iter()
+                .flat_map(|(command, values)| values.into_iter().map(move |v| (offset(command), v)))
+                .collect()),
+            Some(c) => Err((
+                offset(rest.trim_start()),
+                Error::Parse(format!("Unsupported dc command “{c}”")),
+            )),
+        },
+        Err(e) => Err((0, Error::Parse(format!("Invalid dc input: {e}")))),
+    }
+}
+
+/// Strings can span multiple lines, so input ending inside of one has to wait for the next line.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut comment = false;
+    for c in input.chars() {
+        match c {
+            '\n' => comment = false,
+            _ if comment => {}
+            '#' if depth == 0 => comment = true,
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+fn command(input: &str) -> IResult<&str, Vec<V>> {
+    alt((
+        map(number, |n| vec![n]),
+        map(string, |s| vec![s]),
+        register_command,
+        conditional,
+        map(one_of(COMMANDS), simple_command),
+        value(Vec::new(), preceded(char('#'), take_till(|c| c == '\n'))),
+    ))
+    .parse(input)
+}
+
+/// Negative numbers start with `_` because `-` is always subtraction.
+fn number(input: &str) -> IResult<&str, V> {
+    let digits = || take_while1(|c: char| c.is_ascii_digit() || ('A'..='F').contains(&c));
+    map(
+        recognize((
+            opt(char('_')),
+            alt((
+                recognize((digits(), opt((char('.'), opt(digits()))))),
+                recognize((char('.'), digits())),
+            )),
+        )),
+        |n: &str| Literal(n.replacen('_', "-", 1)),
+    )
+    .parse(input)
+}
+
+fn simple_command(c: char) -> Vec<V> {
+    match c {
+        '+' => vec![Add],
+        '-' => vec![Sub],
+        '*' => vec![Mul],
+        '/' => vec![Div],
+        '%' => vec![Mod],
+        '^' => vec![Pow],
+        'v' => vec![Sqrt],
+        // Quotient and remainder
+        '~' => vec![Over, Over, Div, Rot, Rot, Mod],
+        // `dc` prints without popping
+        'p' => vec![Dup, Print],
+        'n' | 'P' => vec![PrintRaw],
+        'f' => vec![Printall],
+        'c' => vec![Clear],
+        'd' => vec![Dup],
+        'r' => vec![Swap],
+        'z' => vec![Stacksize],
+        'k' => vec![SetScale],
+        'K' => vec![GetScale],
+        'i' => vec![SetInputRadix],
+        'I' => vec![GetInputRadix],
+        'o' => vec![SetOutputRadix],
+        'O' => vec![GetOutputRadix],
+        'x' => vec![Execute],
+        'q' => vec![Quit],
+        _ => unreachable!(),
+    }
+}
+
+/// Registers are named by any character, e.g. `sa` stores in register `a`.
+/// The character code is a value instead of a literal so the input radix doesn’t change it.
+fn register(name: char) -> V {
+    Value(Num::int(name as i64, Mode::Decimal))
+}
+
+fn register_command(input: &str) -> IResult<&str, Vec<V>> {
+    map((one_of("sl"), anychar), |(op, name)| {
+        let op = if op == 's' { Store } else { Load };
+        vec![register(name), op]
+    })
+    .parse(input)
+}
+
+/// `<r` executes register `r` if the top of the stack is less than the value below it, `!<r` if it isn’t.
+fn conditional(input: &str) -> IResult<&str, Vec<V>> {
+    map(
+        (opt(char('!')), one_of("<>="), anychar),
+        |(negated, comparison, name)| {
+            let comparison = match comparison {
+                '<' => GreaterThan,
+                '>' => LessThan,
+                _ => Equal,
+            };
+            let run = Quotation(vec![register(name), Load, Execute]);
+            let skip = Quotation(Vec::new());
+            let (then, otherwise) = match negated {
+                Some(_) => (skip, run),
+                None => (run, skip),
+            };
+            vec![comparison, then, otherwise, Conditional]
+        },
+    )
+    .parse(input)
+}
+
+/// Digits may be larger than the radix, e.g. `A` is always 10, so `Ai` resets the input radix.
+/// Numbers keep as many fractional digits as they were written with.
+pub fn parse_number(literal: &str, radix: u32, mode: Mode) -> Num {
+    let (sign, literal) = match literal.strip_prefix('-') {
+        Some(literal) => (-1, literal),
+        None => (1, literal),
+    };
+    let (integer, fraction) = literal.split_once('.').unwrap_or((literal, ""));
+    let digits = integer
+        .chars()
+        .chain(fraction.chars())
+        .filter_map(|c| c.to_digit(16))
+        .fold(BigInt::zero(), |n, digit| n * radix + digit);
+    let denominator = BigInt::from(radix).pow(fraction.len() as u32);
+    let precision = Precision {
+        scale: fraction.len() as i64,
+        rounding: RoundingMode::Down,
+    };
+    Num::from_rational(
+        &BigRational::new(digits * sign, denominator),
+        mode,
+        precision,
+    )
+}
+
+fn scale(d: &BigDecimal) -> i64 {
+    d.fractional_digit_count().max(0)
+}
+
+/// Products keep the fractional digits of both factors, but no more than the scale or the longer factor.
+pub fn mul(a: Num, b: Num, precision: Precision) -> Num {
+    match (a, b) {
+        (Num::Decimal(a), Num::Decimal(b)) => {
+            let digits = (scale(&a) + scale(&b)).min(precision.scale.max(scale(&a)).max(scale(&b)));
+            Num::Decimal((a * b).with_scale_round(digits, RoundingMode::Down))
+        }
+        (a, b) => a * b,
+    }
+}
+
+/// The remainder of the division at the current scale, so `2k 7 3 %` is `.01`.
+pub fn rem(a: Num, b: Num, precision: Precision) -> Result<Num> {
+    match (a, b) {
+        (Num::Decimal(a), Num::Decimal(b)) => {
+            let digits = (precision.scale + scale(&b)).max(scale(&a));
+            let truncating = Precision {
+                rounding: RoundingMode::Down,
+                ..precision
+            };
+            let Num::Decimal(quotient) =
+                Num::Decimal(a.clone()).div(Num::Decimal(b.clone()), truncating)?
+            else {
+                unreachable!()
+            };
+            Ok(Num::Decimal(
+                (a - quotient * b).with_scale_round(digits, RoundingMode::Down),
+            ))
+        }
+        (a, b) => a.rem(b, precision),
+    }
+}
+
+/// Positive powers are truncated like repeated multiplication.
+pub fn pow(a: Num, b: Num, precision: Precision) -> Result<Option<Num>> {
+    match (a, b) {
+        (Num::Decimal(a), Num::Decimal(b)) if b.is_integer() && b.is_positive() => {
+            let exponent = b.to_i64().unwrap_or(i64::MAX);
+            let digits = scale(&a)
+                .saturating_mul(exponent)
+                .min(precision.scale.max(scale(&a)));
+            let power = Num::Decimal(a).pow(Num::Decimal(b), precision)?;
+            Ok(power.map(|n| match n {
+                Num::Decimal(d) => Num::Decimal(d.with_scale_round(digits, RoundingMode::Down)),
+                n => n,
+            }))
+        }
+        (a, b) => a.pow(b, precision),
+    }
+}
+
+/// Roots have as many fractional digits as the scale or the argument, whichever is more.
+pub fn sqrt(n: Num, precision: Precision) -> Option<Num> {
+    match n {
+        Num::Decimal(d) => {
+            let digits = precision.scale.max(scale(&d));
+            let precision = Precision {
+                scale: digits,
+                rounding: RoundingMode::Down,
+            };
+            match Num::Decimal(d).sqrt(precision)? {
+                Num::Decimal(root) => Some(Num::Decimal(
+                    root.with_scale_round(digits, RoundingMode::Down),
+                )),
+                n => Some(n),
+            }
+        }
+        n => n.sqrt(precision),
+    }
+}
+
+/// Numbers are printed with all of their fractional digits, without a zero before the point,
+/// and broken into lines that end with a backslash.
+pub fn format(d: &BigDecimal) -> String {
+    if d.is_zero() {
+        return "0".to_owned();
+    }
+    let plain = d.to_plain_string();
+    let plain = match plain.strip_prefix("0.") {
+        Some(fraction) => format!(".{fraction}"),
+        None => plain.replacen("-0.", "-.", 1),
+    };
+    wrap(&plain)
+}
+
+pub fn wrap(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    chars
+        .chunks(LINE_LENGTH - 1)
+        .map(|line| line.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn lit(s: &str) -> V {
+        Literal(s.to_owned())
+    }
+
+    fn reg(i: i64) -> V {
+        Value(Num::int(i, Mode::Decimal))
+    }
+
+    #[test_case("1 2+p" => vec![lit("1"), lit("2"), Add, Dup, Print]; "arithmetic")]
+    #[test_case("_1.5 .5 1." => vec![lit("-1.5"), lit(".5"), lit("1.")]; "numbers")]
+    #[test_case("12.3.4" => vec![lit("12.3"), lit(".4")]; "adjacent numbers")]
+    #[test_case("16iFF" => vec![lit("16"), SetInputRadix, lit("FF")]; "hex digits")]
+    #[test_case("[1 2+]sa lax" => vec![Str("1 2+".to_owned()), reg(97), Store, reg(97), Load, Execute]; "registers")]
+    #[test_case("l " => vec![reg(32), Load]; "any character is a register")]
+    #[test_case("<a" => vec![
+        GreaterThan,
+        Quotation(vec![reg(97), Load, Execute]),
+        Quotation(vec![]),
+        Conditional,
+    ]; "conditional")]
+    #[test_case("!=a" => vec![
+        Equal,
+        Quotation(vec![]),
+        Quotation(vec![reg(97), Load, Execute]),
+        Conditional,
+    ]; "negated conditional")]
+    #[test_case("1 # 2\n3" => vec![lit("1"), lit("3")]; "comments")]
+    fn lowering(input: &str) -> Vec<V> {
+        parse(input).expect("parsing failed")
+    }
+
+    #[test_case("1 2 a" => "Unsupported dc command “a”")]
+    #[test_case("1 !" => "Unsupported dc command “!”")]
+    fn unsupported(input: &str) -> String {
+        parse(input).unwrap_err().to_string()
+    }
+
+    #[test_case("1 0/" => vec![0, 2, 3]; "commands")]
+    #[test_case("[a b]x\n 2p" => vec![0, 5, 8, 9, 9]; "strings and lines")]
+    #[test_case("1 a" => vec![2]; "errors")]
+    fn offsets(input: &str) -> Vec<usize> {
+        match parse_with_offsets(input) {
+            Ok(values) => values.into_iter().map(|(offset, _)| offset).collect(),
+            Err((offset, _)) => vec![offset],
+        }
+    }
+
+    #[test_case("[abc" => true)]
+    #[test_case("[a[b]c]" => false)]
+    #[test_case("[a\nb]" => false)]
+    #[test_case("# [" => false)]
+    #[test_case("[#\n" => true)]
+    fn incomplete(input: &str) -> bool {
+        is_incomplete(input)
+    }
+
+    #[test_case("0.50" => ".50")]
+    #[test_case("-0.25" => "-.25")]
+    #[test_case("0.000" => "0")]
+    #[test_case("12.3" => "12.3")]
+    fn formatting(n: &str) -> String {
+        format(&n.parse().unwrap())
+    }
+
+    #[test]
+    fn long_lines() {
+        let n = "1".repeat(150);
+        let lines: Vec<_> = format(&n.parse().unwrap()).lines().map(str::len).collect();
+        assert_eq!(lines, vec![70, 70, 12]);
+    }
+}
@@ -0,0 +1,125 @@
+//! Errors, and where in the input they happened.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    StackUnderflow {
+        needed: usize,
+        available: usize,
+    },
+    TypeMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// With the defined names that are spelled similarly, closest first
+    UnknownIdentifier {
+        ident: String,
+        suggestions: Vec<String>,
+    },
+    RegisterOutOfRange(usize),
+    Parse(String),
+    /// Everything else, like division by zero or errors raised with `:throw`
+    Other(String),
+}
+
+impl Error {
+    /// `actual` is shown the way `f` would print it.
+    pub fn type_mismatch<'a>(
+        expected: &str,
+        actual: impl IntoIterator<Item = &'a crate::V>,
+    ) -> Self {
+        let actual: Vec<_> = actual.into_iter().map(|v| v.to_string()).collect();
+        Error::TypeMismatch {
+            expected: expected.to_owned(),
+            actual: actual.join(" "),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::StackUnderflow { needed, available } => write!(
+                f,
+                "not enough elements on the stack, needed {needed} but there were {available}"
+            ),
+            Error::TypeMismatch { expected, actual } => {
+                write!(f, "Expected {expected}, got {actual}")
+            }
+            Error::UnknownIdentifier { ident, suggestions } => {
+                write!(f, "{ident} not found")?;
+                match suggestions.split_last() {
+                    None => Ok(()),
+                    Some((last, [])) => write!(f, ", did you mean {last}?"),
+                    Some((last, rest)) => {
+                        write!(f, ", did you mean {} or {last}?", rest.join(", "))
+                    }
+                }
+            }
+            Error::RegisterOutOfRange(i) => write!(f, "Register {i} out of range"),
+            Error::Parse(message) | Error::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_owned())
+    }
+}
+
+/// An error together with the line of input that caused it, shown with a caret under the failing token.
+#[derive(Debug)]
+pub struct Located {
+    pub error: Error,
+    pub line: usize,
+    pub column: usize,
+    text: String,
+}
+
+impl Located {
+    /// `offset` is the byte offset of the failing token in `source`, whose first line has the number `first_line`.
+    pub fn new(error: Error, source: &str, offset: usize, first_line: usize) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Located {
+            error,
+            line: first_line + before.matches('\n').count(),
+            column: before[line_start..].chars().count() + 1,
+            text: source[line_start..].lines().next().unwrap_or("").to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Error at {}:{}: {}\n{}\n{}^",
+            self.line,
+            self.column,
+            self.error,
+            self.text,
+            " ".repeat(self.column - 1)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("1 0 /", 4, 1 => "Error at 1:5: Division by zero\n1 0 /\n    ^"; "single line")]
+    #[test_case("[a\nb] 0 /", 7, 3 => "Error at 4:5: Division by zero\nb] 0 /\n    ^"; "later line")]
+    #[test_case("[ä] 0 /", 7, 1 => "Error at 1:7: Division by zero\n[ä] 0 /\n      ^"; "columns count characters")]
+    fn rendering(source: &str, offset: usize, first_line: usize) -> String {
+        Located::new("Division by zero".into(), source, offset, first_line).to_string()
+    }
+}
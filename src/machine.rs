@@ -1,25 +1,69 @@
 use crate::{
+    dc::{self, Dialect},
+    error::Error,
+    num::{check_power, nonzero, parse_complex, parse_rational, Angle},
     parser::parse,
-    Num, Result,
+    Mode, Notation, Num, Precision, Result,
     V::{self, *},
 };
-use std::{collections::HashMap, ops};
+use bigdecimal::{num_bigint::BigInt, RoundingMode};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::{cmp::Ordering, collections::HashMap, io::Write, ops::Range};
 
-const STACK_EMPTY: &str = "not enough elements on the stack";
+const MAX_ITERATIONS: usize = 1_000_000;
+// Ten times as many digits of π or a square root would already take seconds.
+const MAX_SCALE: i64 = 10_000;
+// A number with this many bits already takes 2 MB.
+const MAX_SHIFT: usize = 1 << 24;
+// Functions that aren’t tail calls, e.g. the first of a composition, can nest this deep.
+const MAX_DEPTH: usize = 10_000;
+/// Enough for `MAX_DEPTH` nested calls, even in debug builds. Only the pages that are used get allocated.
+pub const STACK_SIZE: usize = 1 << 30;
 // Index 256 and above are for internal use.
 const NUM_REGISTERS: usize = 266;
 
 pub struct Machine {
     pub stack: Vec<V>,
-    registers: [Num; NUM_REGISTERS],
-    vars: HashMap<String, V>,
+    registers: [V; NUM_REGISTERS],
+    // The global variables first, then one scope for each `{}` function that is running
+    scopes: Vec<HashMap<String, V>>,
+    // The scope that identifiers are looked up in before the global one and that `s` defines them in
+    local: usize,
+    mode: Mode,
+    precision: Precision,
+    input_radix: u32,
+    output_radix: u32,
+    notation: Notation,
+    angle: Angle,
+    dialect: Dialect,
+    max_iterations: usize,
+    // How many calls of `process2` are running
+    depth: usize,
+    journal: Journal,
+    // Where the last error happened: the operation that failed and the identifiers it was called from
+    trace: Vec<V>,
+}
+
+/// What the current top-level `process` call changed, so it can be undone if it fails.
+/// Only the values that are actually overwritten or popped are saved, not the whole state.
+#[derive(Default)]
+struct Journal {
+    /// Everything below this index is still the same as before
+    watermark: usize,
+    /// The values that were at and above the watermark, top first
+    stack: Vec<V>,
+    /// The values from before the first change of each register and global variable
+    registers: Vec<(usize, V)>,
+    vars: Vec<(String, Option<V>)>,
 }
 
 macro_rules! pop {
     ($expects:literal, $machine:ident, $pattern:pat => $f:expr) => {{
         let vals = $machine.popn()?;
         let $pattern = vals else {
-            let e = format!(concat!("Expected ", $expects, ", got {:?}"), vals);
+            let e = Error::type_mismatch($expects, &vals);
             for val in vals {
                 $machine.push(val);
             }
@@ -29,12 +73,37 @@ macro_rules! pop {
     }};
 }
 
+/// Any kind of number, for `pop!`.
+macro_rules! number {
+    () => {
+        Value(_) | Rational(_) | Complex(_)
+    };
+}
+
 impl Machine {
-    pub fn new() -> Self {
+    pub fn new(mode: Mode, dialect: Dialect) -> Self {
         let mut machine = Self {
             stack: Vec::new(),
-            registers: [0.0; _],
-            vars: HashMap::new(),
+            registers: std::array::from_fn(|_| Value(Num::int(0, mode))),
+            scopes: vec![HashMap::new()],
+            local: 0,
+            mode,
+            precision: match dialect {
+                Dialect::Dcr => Precision::default(),
+                Dialect::Dc => Precision {
+                    scale: 0,
+                    ..Precision::default()
+                },
+            },
+            input_radix: 10,
+            output_radix: 10,
+            notation: Notation::default(),
+            angle: Angle::default(),
+            dialect,
+            max_iterations: MAX_ITERATIONS,
+            depth: 0,
+            journal: Journal::default(),
+            trace: Vec::new(),
         };
         for line in crate::stdlib::STDLIB.lines() {
             for v in parse(line)
@@ -49,25 +118,307 @@ impl Machine {
         machine
     }
 
+    /// Either runs `v` completely, or, if it fails, leaves the stack, registers and variables as they were.
     pub fn process(&mut self, v: V) -> Result<()> {
-        self.process2::<false>(v)
+        self.journal = Journal {
+            watermark: self.stack.len(),
+            ..Journal::default()
+        };
+        self.trace.clear();
+        let result = self.process2::<false>(v);
+        if result.is_err() {
+            self.rollback();
+        }
+        result
     }
 
-    fn uncurry(&mut self, v: V) -> Result<()> {
-        match v {
-            Curried(fun, arg) => {
-                self.push(*arg);
-                self.uncurry(*fun)
-            }
-            v => self.process2::<true>(v),
+    /// After `process` failed, the operation that failed and the identifiers of the functions
+    /// it was in, innermost first. Functions that made a tail call are already gone.
+    pub fn trace(&self) -> impl Iterator<Item = &V> {
+        self.trace.iter()
+    }
+
+    fn rollback(&mut self) {
+        let journal = std::mem::take(&mut self.journal);
+        self.stack.truncate(journal.watermark);
+        self.stack.extend(journal.stack.into_iter().rev());
+        for (addr, v) in journal.registers {
+            self.registers[addr] = v;
+        }
+        for (ident, v) in journal.vars {
+            match v {
+                Some(v) => self.scopes[0].insert(ident, v),
+                None => self.scopes[0].remove(&ident),
+            };
+        }
+    }
+
+    /// Has to be called before anything at or above `index` is removed from the stack.
+    fn save_stack(&mut self, index: usize) {
+        let journal = &mut self.journal;
+        if index < journal.watermark {
+            let saved = self.stack[index..journal.watermark].iter().rev().cloned();
+            journal.stack.extend(saved);
+            journal.watermark = index;
         }
     }
 
+    fn define(&mut self, scope: usize, ident: String, value: V) {
+        let old = self.scopes[scope].insert(ident.clone(), value);
+        if scope == 0 && !self.journal.vars.iter().any(|(saved, _)| *saved == ident) {
+            self.journal.vars.push((ident, old));
+        }
+    }
+
+    /// Whatever is applied last, like the last token of a quotation or the second function of a composition,
+    /// is a tail call that runs in the loop instead of recursing, so deep recursion doesn’t overflow the stack.
     fn process2<const APPLY: bool>(&mut self, v: V) -> Result<()> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("Recursion is deeper than {MAX_DEPTH} calls").into());
+        }
+        self.depth += 1;
+        // Functions entered by this call are left when it returns.
+        let (scopes, local) = (self.scopes.len(), self.local);
+        // The identifier this call last jumped to, which is where it is if it fails
+        let mut current = None;
+        let (mut v, mut apply) = (v, APPLY);
+        let run = || loop {
+            (v, apply) = match v {
+                v @ (Value(_) | Rational(_) | Complex(_) | Str(_)) => return Ok(self.push(v)),
+                v @ (Fun(_) | Identifier(_) | Global(_) | Quotation(_) | Lambda(..)) if !apply => {
+                    return Ok(self.push(v))
+                }
+                Quotation(mut code) => {
+                    let Some(last) = code.pop() else {
+                        return Ok(());
+                    };
+                    for v in code {
+                        self.process2::<false>(v)?;
+                    }
+                    (last, false)
+                }
+                // Strings are executed like in `dc`, anything else is applied like with `$`.
+                Execute => match self.pop()? {
+                    Str(s) => (self.quotation(&s)?, true),
+                    v => (v, true),
+                },
+
+                Apply => (self.pop()?, true),
+                // Identifiers are only resolved when they are applied, so functions can refer to themselves.
+                v @ (Identifier(_) | Global(_)) => {
+                    let f = self.load(&v)?;
+                    self.leave_local_scope(&f);
+                    current = Some(v);
+                    (f, true)
+                }
+                Fun(o) => (*o, false),
+                Lambda(params, body) => {
+                    self.enter(params, scopes)?;
+                    (*body, true)
+                }
+                // Curried arguments are pushed in reverse order before the function is applied.
+                Curried(fun, arg) => {
+                    self.push(*arg);
+                    (*fun, true)
+                }
+                Composed(a, b) => {
+                    self.process2::<true>(*a)?;
+                    (*b, true)
+                }
+
+                Load => {
+                    let addr = self.pop()?;
+                    let f = self.load(&addr)?;
+                    if apply {
+                        self.leave_local_scope(&f);
+                    }
+                    (f, apply)
+                }
+                Repeat => {
+                    let [v, repetitions] = self.popn()?;
+                    for _ in 0..repetitions.int()? {
+                        self.process2::<true>(v.clone())?;
+                    }
+                    return Ok(());
+                }
+                // Quotations are executed, so only the chosen branch has any effect.
+                Conditional => match self.pop_branch()? {
+                    q @ Quotation(_) => (q, true),
+                    v => return Ok(self.push(v)),
+                },
+                // Like `?`, but any function is applied and only the chosen one is evaluated.
+                If => (self.pop_branch()?, true),
+                While => return self.repeat_while(false),
+                Until => return self.repeat_while(true),
+                Try => return self.attempt(),
+                v @ (Map | Filter | Fold | Scan | SortBy) => return self.higher_order(v),
+                v @ (Literal(_) | List(_)) => {
+                    let v = self.literal(v)?;
+                    return Ok(self.push(v));
+                }
+                // Apart from literals, operators are cheap to copy for the trace.
+                v => {
+                    let op = v.clone();
+                    return self.operator(v).inspect_err(|_| self.trace.push(op));
+                }
+            }
+        };
+        let result = run();
+        self.depth -= 1;
+        self.scopes.truncate(scopes);
+        self.local = local;
+        if let (Err(_), Some(current)) = (&result, current) {
+            self.trace.push(current);
+        }
+        result
+    }
+
+    /// List operations that apply a function to the elements, e.g. `'(1 2 3) \*2@ :map`.
+    /// Like `operator`, kept out of `process2` to keep its stack frames small.
+    #[inline(never)]
+    fn higher_order(&mut self, v: V) -> Result<()> {
         Ok(match v {
-            v @ Value(_) => self.stack.push(v),
-            v @ (Fun(_) | Identifier(_)) if !APPLY => self.stack.push(v),
+            Map => pop!("a list and a function", self, [List(items), f] => {
+                let items = items
+                    .into_iter()
+                    .map(|x| self.call(&f, [x]))
+                    .collect::<Result<_>>()?;
+                self.push(List(items))
+            }),
+            Filter => pop!("a list and a function", self, [List(items), f] => {
+                let mut kept = Vec::new();
+                for x in items {
+                    match self.call(&f, [x.clone()])? {
+                        v @ number!() if v.is_zero() => {}
+                        number!() => kept.push(x),
+                        v => return Err(Error::type_mismatch("a number from the filter", [&v])),
+                    }
+                }
+                self.push(List(kept))
+            }),
+            Fold => {
+                pop!("a list, an initial value and a function", self, [List(items), init, f] => {
+                    let mut acc = init;
+                    for x in items {
+                        acc = self.call(&f, [acc, x])?;
+                    }
+                    self.push(acc)
+                })
+            }
+            Scan => {
+                pop!("a list, an initial value and a function", self, [List(items), init, f] => {
+                    let mut acc = init;
+                    let mut steps = Vec::with_capacity(items.len());
+                    for x in items {
+                        acc = self.call(&f, [acc, x])?;
+                        steps.push(acc.clone());
+                    }
+                    self.push(List(steps))
+                })
+            }
+            SortBy => pop!("a list and a function", self, [List(items), f] => {
+                let mut keyed = Vec::with_capacity(items.len());
+                for x in items {
+                    keyed.push((self.call(&f, [x.clone()])?, x));
+                }
+                let keyed = sort(keyed, |(key, _)| key)?;
+                self.push(List(keyed.into_iter().map(|(_, x)| x).collect()))
+            }),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Apply the body as long as the condition leaves a nonzero value (zero for `:until`) on the stack.
+    /// Loops that don’t finish within `max_iterations` are an error instead of hanging.
+    #[inline(never)]
+    fn repeat_while(&mut self, until: bool) -> Result<()> {
+        let [condition, body] = self.popn()?;
+        let mut iterations = 0;
+        loop {
+            match self.call(&condition, [])? {
+                v @ number!() if v.is_zero() == until => {}
+                number!() => return Ok(()),
+                v => {
+                    return Err(Error::type_mismatch(
+                        "a number from the loop condition",
+                        [&v],
+                    ))
+                }
+            }
+            if iterations == self.max_iterations {
+                return Err(
+                    format!("The loop didn’t finish within {iterations} iterations").into(),
+                );
+            }
+            self.process2::<true>(body.clone())?;
+            iterations += 1;
+        }
+    }
+
+    /// Apply a function, and if it fails, reset the stack to how it was before and apply the handler,
+    /// with the error message on top of the stack.
+    #[inline(never)]
+    fn attempt(&mut self) -> Result<()> {
+        let [body, handler] = self.popn()?;
+        let stack = self.stack.clone();
+        let depth = self.trace.len();
+        if let Err(e) = self.process2::<true>(body) {
+            self.trace.truncate(depth);
+            self.save_stack(0);
+            self.stack = stack;
+            self.push(Str(e.to_string()));
+            self.process2::<true>(handler)?;
+        }
+        Ok(())
+    }
+
+    /// Enter a function: its parameters are bound to the top values of the stack in a new scope.
+    /// `scopes` is how many there were when the current `process2` call started, any beyond that
+    /// belong to a function that made this a tail call and is done.
+    fn enter(&mut self, params: Vec<String>, scopes: usize) -> Result<()> {
+        if params.len() > self.stack.len() {
+            return Err(Error::StackUnderflow {
+                needed: params.len(),
+                available: self.stack.len(),
+            });
+        }
+        self.save_stack(self.stack.len() - params.len());
+        let args = self.stack.split_off(self.stack.len() - params.len());
+        self.scopes.truncate(scopes);
+        self.scopes.push(params.into_iter().zip(args).collect());
+        self.local = self.scopes.len() - 1;
+        Ok(())
+    }
+
+    /// Before applying `f` from a variable or register. Only `{}` functions have a scope, anything
+    /// else, like a composition, runs in the global one instead of that of whoever applies it.
+    fn leave_local_scope(&mut self, f: &V) {
+        if !matches!(f, Lambda(..)) {
+            self.local = 0;
+        }
+    }
 
+    /// Apply `f` to `args` and return its result. `f` has to replace its arguments with exactly one value.
+    fn call<const N: usize>(&mut self, f: &V, args: [V; N]) -> Result<V> {
+        let len = self.stack.len();
+        self.stack.extend(args);
+        self.process2::<true>(f.clone())?;
+        if self.stack.len() != len + 1 {
+            let change = self.stack.len() as isize - len as isize;
+            return Err(format!(
+                "Expected {f} to turn {N} arguments into 1 value, but the stack size changed by {change:+} instead of +1"
+            )
+            .into());
+        }
+        self.pop()
+    }
+
+    /// Everything that doesn’t call back into `process2`. Kept separate so the recursive calls
+    /// of quotations and functions don’t need stack space for the locals of every operator.
+    #[inline(never)]
+    fn operator(&mut self, v: V) -> Result<()> {
+        Ok(match v {
+            Quote => pop!("a string", self, [Str(s)] => self.push(self.quotation(&s)?)),
             Curry => {
                 let [a, b] = self.popn()?;
                 self.push(Curried(
@@ -79,107 +430,712 @@ impl Machine {
                     Box::new(b),
                 ));
             }
-            Apply => {
-                let next = self.pop()?;
-                self.process2::<true>(next)?
+            Bind(params) => {
+                let body = self.pop()?;
+                self.push(Lambda(params, Box::new(body)))
             }
-            Identifier(ident) => match self.vars.get(&ident).cloned() {
-                Some(v) => self.process2::<true>(v)?,
-                None => return Err(format!("{ident} not found")),
-            },
-            Fun(o) => self.process(*o)?,
-            c @ Curried(_, _) => self.uncurry(c)?,
             Compose => {
-                let [mut a, mut b] = self.popn()?;
-                a = if let Identifier(id) = a {
-                    self.vars[&id].clone()
-                } else {
-                    a
+                let [a, b] = self.popn()?;
+                self.push(Composed(Box::new(a), Box::new(b)))
+            }
+
+            Add => self.binop(|a, b| Ok(a + b), |a, b| Ok(a + b), |a, b| a + b)?,
+            Sub => self.binop(|a, b| Ok(a - b), |a, b| Ok(a - b), |a, b| a - b)?,
+            Mul => {
+                let (dialect, precision) = (self.dialect, self.precision);
+                self.binop(
+                    |a, b| match dialect {
+                        Dialect::Dcr => Ok(a * b),
+                        Dialect::Dc => Ok(dc::mul(a, b, precision)),
+                    },
+                    |a, b| Ok(a * b),
+                    |a, b| a * b,
+                )?
+            }
+            // Dividing integers is exact, e.g. `1 3 /` is `1/3`.
+            Div if self.dialect == Dialect::Dcr && self.integers_on_top() => {
+                pop!("two integers", self, [Value(a), Value(b)] => {
+                    let r = a.to_rational()? / nonzero(b.to_rational()?)?;
+                    self.push(if r.is_integer() {
+                        Value(Num::from_bigint(r.to_integer(), self.mode))
+                    } else {
+                        Rational(r)
+                    })
+                })
+            }
+            Div => {
+                let precision = self.precision;
+                self.binop(
+                    |a, b| a.div(b, precision),
+                    |a, b| Ok(a / nonzero(b)?),
+                    |a, b| a / b,
+                )?
+            }
+            Mod => {
+                let (dialect, precision) = (self.dialect, self.precision);
+                self.binop(
+                    |a, b| match dialect {
+                        Dialect::Dcr => a.rem(b, precision),
+                        Dialect::Dc => dc::rem(a, b, precision),
+                    },
+                    |a, b| Ok(a % nonzero(b)?),
+                    |a, b| a % b,
+                )?
+            }
+            Abs => {
+                let mode = self.mode;
+                self.unop(
+                    |n| Value(n.abs()),
+                    |r| Rational(r.abs()),
+                    |c| Value(Num::from_f64(c.norm(), mode)),
+                )?
+            }
+            Arg => {
+                let mode = self.mode;
+                self.unop(
+                    |n| Value(Num::from_f64(Complex64::new(n.to_f64(), 0.0).arg(), mode)),
+                    |r| {
+                        let r = r.to_f64().unwrap_or(f64::NAN);
+                        Value(Num::from_f64(Complex64::new(r, 0.0).arg(), mode))
+                    },
+                    |c| Value(Num::from_f64(c.arg(), mode)),
+                )?
+            }
+            Conj => self.unop(Value, Rational, |c| Complex(c.conj()))?,
+            Re => {
+                let mode = self.mode;
+                self.unop(Value, Rational, |c| Value(Num::from_f64(c.re, mode)))?
+            }
+            Im => {
+                let mode = self.mode;
+                self.unop(
+                    |_| Value(Num::int(0, mode)),
+                    |_| Rational(BigRational::zero()),
+                    |c| Value(Num::from_f64(c.im, mode)),
+                )?
+            }
+            Sqrt => {
+                let (dialect, mode, precision) = (self.dialect, self.mode, self.precision);
+                let root = |n: Num| match dialect {
+                    Dialect::Dcr => n.sqrt(precision),
+                    Dialect::Dc => dc::sqrt(n, precision),
+                };
+                let sqrt = |n: Num| match root(n.clone()) {
+                    Some(root) => Value(root),
+                    None => Complex(Complex64::new(n.to_f64(), 0.0).sqrt()),
                 };
-                b = if let Identifier(id) = b {
-                    self.vars[&id].clone()
+                self.unop(
+                    sqrt,
+                    |r| sqrt(Num::from_rational(&r, mode, precision)),
+                    |c| Complex(c.sqrt()),
+                )?
+            }
+            Pow => self.pow()?,
+            Ln => self.float_op(f64::ln, Complex64::ln)?,
+            Log => self.float_op(f64::log10, |c| c.log10())?,
+            Exp => self.float_op(f64::exp, Complex64::exp)?,
+            Sin => {
+                let k = self.angle.to_radians();
+                self.float_op(|x| (x * k).sin(), |c| (c * k).sin())?
+            }
+            Cos => {
+                let k = self.angle.to_radians();
+                self.float_op(|x| (x * k).cos(), |c| (c * k).cos())?
+            }
+            Tan => {
+                let k = self.angle.to_radians();
+                self.float_op(|x| (x * k).tan(), |c| (c * k).tan())?
+            }
+            Asin => {
+                let k = self.angle.to_radians();
+                self.float_op(|x| x.asin() / k, |c| c.asin() / k)?
+            }
+            Acos => {
+                let k = self.angle.to_radians();
+                self.float_op(|x| x.acos() / k, |c| c.acos() / k)?
+            }
+            Atan => {
+                let k = self.angle.to_radians();
+                self.float_op(|x| x.atan() / k, |c| c.atan() / k)?
+            }
+            Pi => self.push(Value(Num::pi(self.mode, self.precision))),
+            Euler => self.push(Value(Num::e(self.mode, self.precision))),
+            Degrees => self.angle = Angle::Degrees,
+            Radians => self.angle = Angle::Radians,
+            And => self.int_binop(|a, b| Ok(a & b))?,
+            Or => self.int_binop(|a, b| Ok(a | b))?,
+            Xor => self.int_binop(|a, b| Ok(a ^ b))?,
+            Not => {
+                let n = self.pop()?.integer()?;
+                self.push(Value(Num::from_bigint(!n, self.mode)))
+            }
+            ShiftLeft => self.int_binop(shift)?,
+            ShiftRight => self.int_binop(|a, b| shift(a, -b))?,
+            Popcount => {
+                let n = self.pop()?.integer()?;
+                if n.is_negative() {
+                    return Err(format!("{n} has infinitely many ones").into());
+                }
+                let ones = n.magnitude().count_ones() as i64;
+                self.push(Value(Num::int(ones, self.mode)))
+            }
+            // Truncating like `%`, so `a = b * (a :div b) + a % b`.
+            IntDiv => self.int_binop(|a, b| {
+                if b.is_zero() {
+                    Err("Division by zero".into())
                 } else {
-                    b
+                    Ok(a / b)
+                }
+            })?,
+            Floor => self.round_to_integer(RoundingMode::Floor)?,
+            Ceil => self.round_to_integer(RoundingMode::Ceiling)?,
+            Trunc => self.round_to_integer(RoundingMode::Down)?,
+            Round => self.round_to_integer(RoundingMode::HalfUp)?,
+            ToDecimal => {
+                let v = match self.pop()? {
+                    Rational(r) => Value(Num::from_rational(&r, self.mode, self.precision)),
+                    v @ Value(_) => v,
+                    v => return Err(Error::type_mismatch("a number", [&v])),
                 };
-                self.push(Composed(Box::new(a), Box::new(b)))
+                self.push(v)
             }
-
-            Composed(a, b) => {
-                self.process2::<true>(*a)?;
-                self.process2::<true>(*b)?;
+            ToRational => {
+                let v = match self.pop()? {
+                    Value(n) => Rational(n.to_rational()?),
+                    v @ Rational(_) => v,
+                    v => return Err(Error::type_mismatch("a number", [&v])),
+                };
+                self.push(v)
             }
 
-            Add => self.binop(ops::Add::add)?,
-            Sub => self.binop(ops::Sub::sub)?,
-            Mul => self.binop(ops::Mul::mul)?,
-            Div => self.binop(ops::Div::div)?,
-            Mod => self.binop(|a, b| a % b)?,
-
             Store => {
                 let [value, addr] = self.popn()?;
-                if let Identifier(ident) = addr {
-                    self.vars.insert(ident, value);
-                } else {
-                    let addr = addr.int()?;
-                    *self.reg(addr)? = value.number()?;
+                match addr {
+                    // Definitions are local to the function that makes them
+                    Identifier(ident) => self.define(self.local, ident, value),
+                    Global(ident) => self.define(0, ident, value),
+                    addr => {
+                        let addr = addr.int()?;
+                        let old = std::mem::replace(self.reg(addr)?, value);
+                        if !self
+                            .journal
+                            .registers
+                            .iter()
+                            .any(|(saved, _)| *saved == addr)
+                        {
+                            self.journal.registers.push((addr, old));
+                        }
+                    }
                 }
             }
-            Load => {
-                let addr = self.pop()?;
-                let v = if let Identifier(ident) = addr {
-                    self.vars
-                        .get(&ident)
-                        .cloned()
-                        .ok_or_else(|| format!("{ident} not found"))?
-                } else {
-                    let addr = addr.int()?;
-                    Value(*self.reg(addr)?)
+            Stacksize => self
+                .stack
+                .push(Value(Num::int(self.stack.len() as i64, self.mode))),
+            LessThan => self.compare(Ordering::is_lt)?,
+            GreaterThan => self.compare(Ordering::is_gt)?,
+            Equal => self.compare(Ordering::is_eq)?,
+            Clear => {
+                self.save_stack(0);
+                self.stack.clear()
+            }
+            Concat => match self.popn()? {
+                [List(mut a), List(b)] => {
+                    a.extend(b);
+                    self.push(List(a))
+                }
+                [a, b] => {
+                    let s = self.show(&a) + &self.show(&b);
+                    self.push(Str(s))
+                }
+            },
+            Length => {
+                let length = match self.pop()? {
+                    Str(s) => s.chars().count(),
+                    List(items) => items.len(),
+                    v => return Err(Error::type_mismatch("a string or a list", [&v])),
                 };
-                self.process2::<APPLY>(v)?
-            }
-            Stacksize => self.stack.push(Value(self.stack.len() as f64)),
-            Repeat => {
-                let [v, repetitions] = self.popn()?;
-                for _ in 0..repetitions.int()? {
-                    self.process2::<true>(v.clone())?;
-                }
-            }
-            LessThan => self.binop(|a, b| f64::from(a < b))?,
-            GreaterThan => self.binop(|a, b| f64::from(a > b))?,
-            Equal => self.binop(|a, b| f64::from(a == b))?,
-            Conditional => pop!("a number and 2 branches", self, [Value(condition), a, b] => {
-                self.push(
-                    if condition == 0.0 {
-                        b
-                    } else {
-                        a
-                    }
-                )
+                self.push(Value(Num::int(length as i64, self.mode)))
+            }
+            Substring => {
+                pop!("a string, a start and a length", self, [Str(s), start @ number!(), length @ number!()] => {
+                    let range = slice(start, length, s.chars().count())?;
+                    self.push(Str(s.chars().skip(range.start).take(range.len()).collect()))
+                })
+            }
+            Pack => {
+                let n = self.pop()?.integer()?;
+                let Some(n) = n.to_usize() else {
+                    return Err(format!("Cannot pack {n} elements").into());
+                };
+                if n > self.stack.len() {
+                    return Err(Error::StackUnderflow {
+                        needed: n,
+                        available: self.stack.len(),
+                    });
+                }
+                self.save_stack(self.stack.len() - n);
+                let items = self.stack.split_off(self.stack.len() - n);
+                self.push(List(items))
+            }
+            Unpack => pop!("a list", self, [List(items)] => {
+                let n = items.len() as i64;
+                self.stack.extend(items);
+                self.push(Value(Num::int(n, self.mode)))
+            }),
+            Get => pop!("a list and an index", self, [List(mut items), i @ number!()] => {
+                let i = i.integer()?;
+                // Negative indices count from the end.
+                let offset = if i.is_negative() { items.len() } else { 0 };
+                let index = i
+                    .to_isize()
+                    .and_then(|i| offset.checked_add_signed(i))
+                    .filter(|&index| index < items.len());
+                let Some(index) = index else {
+                    let len = items.len();
+                    return Err(format!("Index {i} is out of bounds for a list of length {len}").into());
+                };
+                self.push(items.swap_remove(index))
+            }),
+            Append => pop!("a list and a value", self, [List(mut items), v] => {
+                items.push(v);
+                self.push(List(items))
+            }),
+            Slice => {
+                pop!("a list, a start and a length", self, [List(items), start @ number!(), length @ number!()] => {
+                    let range = slice(start, length, items.len())?;
+                    self.push(List(items[range].to_vec()))
+                })
+            }
+            Zip => pop!("two lists", self, [List(a), List(b)] => {
+                let pairs = a.into_iter().zip(b).map(|(a, b)| List(vec![a, b])).collect();
+                self.push(List(pairs))
+            }),
+            Sort => pop!("a list", self, [List(items)] => self.push(List(sort(items, |x| x)?))),
+            Range => pop!("a start and an end", self, [Value(start), Value(end)] => {
+                self.push(List(range(start, end, Num::int(1, self.mode), self.max_iterations)?))
             }),
-            Clear => self.stack.clear(),
+            RangeBy => {
+                pop!("a start, an end and a step", self, [Value(start), Value(end), Value(step)] => {
+                    self.push(List(range(start, end, step, self.max_iterations)?))
+                })
+            }
+            Linspace => {
+                pop!("a start, an end and a count", self, [Value(start), Value(end), count @ number!()] => {
+                    let count = count.integer()?;
+                    let Some(n) = count.to_i64().filter(|&n| n >= 0) else {
+                        return Err(format!("Cannot make a list of {count} elements").into());
+                    };
+                    // Like ranges, limited to as many elements as a loop can have iterations
+                    if n as usize > self.max_iterations {
+                        let max = self.max_iterations;
+                        return Err(format!("Cannot make a list of {count} elements, that is more than the limit of {max}").into());
+                    }
+                    let step = match n {
+                        0 | 1 => Num::int(0, self.mode),
+                        n => (end.clone() - start.clone()).div(Num::int(n - 1, self.mode), self.precision)?,
+                    };
+                    // The end is added as it is so that rounding the step doesn’t move it
+                    let items = (0..n)
+                        .map(|i| match i {
+                            i if i > 0 && i == n - 1 => Value(end.clone()),
+                            i => Value(start.clone() + step.clone() * Num::int(i, self.mode)),
+                        })
+                        .collect();
+                    self.push(List(items))
+                })
+            }
+            Dup => {
+                let [v] = self.popn()?;
+                self.push(v.clone());
+                self.push(v)
+            }
+            Swap => {
+                let [a, b] = self.popn()?;
+                self.push(b);
+                self.push(a)
+            }
+            Drop => drop(self.pop()?),
+            Over => {
+                let [a, b] = self.popn()?;
+                self.push(a.clone());
+                self.push(b);
+                self.push(a)
+            }
+            Rot => {
+                let [a, b, c] = self.popn()?;
+                self.push(b);
+                self.push(c);
+                self.push(a)
+            }
+            Pick => {
+                let i = self.pop_depth()?;
+                self.push(self.stack[i].clone())
+            }
+            Roll => {
+                let i = self.pop_depth()?;
+                self.save_stack(i);
+                let v = self.stack.remove(i);
+                self.push(v)
+            }
 
-            Print => println!("{}", self.pop()?),
+            SetScale => {
+                let scale = self.pop()?.integer()?;
+                self.precision.scale = match scale.to_i64() {
+                    Some(scale @ 0..=MAX_SCALE) => scale,
+                    _ => {
+                        return Err(format!("Scale {scale} is not between 0 and {MAX_SCALE}").into())
+                    }
+                };
+            }
+            SetMaxIterations => self.max_iterations = self.pop()?.int()?,
+            GetScale => self.push(Value(Num::int(self.precision.scale, self.mode))),
+            SetRounding => {
+                let mode = self.pop()?.int()?;
+                self.precision.set_rounding(mode)?
+            }
+            SetInputRadix => self.input_radix = self.pop_radix()?,
+            GetInputRadix => self.push(Value(Num::int(self.input_radix.into(), self.mode))),
+            SetOutputRadix => self.output_radix = self.pop_radix()?,
+            GetOutputRadix => self.push(Value(Num::int(self.output_radix.into(), self.mode))),
+            SetNotation => self.notation = self.pop()?.int()?.try_into()?,
+
+            Print => {
+                let v = self.pop()?;
+                println!("{}", self.show(&v))
+            }
+            PrintRaw => {
+                let v = self.pop()?;
+                print!("{}", self.show(&v));
+                std::io::stdout().flush().map_err(|e| e.to_string())?
+            }
+            // `dc` prints one value per line, starting at the top.
+            Printall if self.dialect == Dialect::Dc => {
+                for v in self.stack.iter().rev() {
+                    println!("{}", self.show(v))
+                }
+            }
             Printall => println!(
                 "{}",
                 self.stack
                     .iter()
-                    .map(|x| format!("{x} "))
+                    .map(|x| format!("{} ", self.show(x)))
                     .collect::<String>()
             ),
             Quit => std::process::exit(0),
+            Throw => {
+                let v = self.pop()?;
+                return Err(Error::Other(self.show(&v)));
+            }
+            v => unreachable!("{v:?} is handled by process2"),
+        })
+    }
+
+    fn unop<F, G, H>(&mut self, num: F, rational: G, complex: H) -> Result<()>
+    where
+        F: FnOnce(Num) -> V,
+        G: FnOnce(BigRational) -> V,
+        H: FnOnce(Complex64) -> V,
+    {
+        pop!("a number", self, [v @ number!()] => {
+            let v = match v {
+                Value(n) => num(n),
+                Rational(r) => rational(r),
+                Complex(c) => complex(c),
+                _ => unreachable!(),
+            };
+            Ok(self.push(v))
+        })
+    }
+
+    fn binop<F, G, H>(&mut self, num: F, rational: G, complex: H) -> Result<()>
+    where
+        F: FnOnce(Num, Num) -> Result<Num>,
+        G: FnOnce(BigRational, BigRational) -> Result<BigRational>,
+        H: FnOnce(Complex64, Complex64) -> Complex64,
+    {
+        pop!("two numbers", self, [a @ number!(), b @ number!()] => {
+            let v = match Pair::new(a, b)? {
+                Pair::Nums(a, b) => Value(num(a, b)?),
+                Pair::Rationals(a, b) => Rational(rational(a, b)?),
+                Pair::Complexes(a, b) => Complex(complex(a, b)),
+            };
+            Ok(self.push(v))
+        })
+    }
+
+    /// Raising to integer powers keeps rationals exact, negative bases with fractional exponents become complex.
+    fn pow(&mut self) -> Result<()> {
+        let (dialect, mode, precision) = (self.dialect, self.mode, self.precision);
+        let real = |a: Num, b: Num| -> Result<V> {
+            let power = match dialect {
+                Dialect::Dcr => a.clone().pow(b.clone(), precision)?,
+                Dialect::Dc => dc::pow(a.clone(), b.clone(), precision)?,
+            };
+            Ok(match power {
+                Some(n) => Value(n),
+                None => Complex(Complex64::new(a.to_f64(), 0.0).powf(b.to_f64())),
+            })
+        };
+        pop!("two numbers", self, [a @ number!(), b @ number!()] => {
+            let v = match Pair::new(a, b)? {
+                Pair::Nums(a, b) => real(a, b)?,
+                Pair::Rationals(a, b) if b.is_integer() => {
+                    let e = b
+                        .to_integer()
+                        .to_i32()
+                        .ok_or_else(|| format!("Exponent {b} is too large"))?;
+                    check_power(a.numer(), e.unsigned_abs().into())?;
+                    check_power(a.denom(), e.unsigned_abs().into())?;
+                    Rational(if e < 0 { nonzero(a)? } else { a }.pow(e))
+                }
+                Pair::Rationals(a, b) => real(
+                    Num::from_rational(&a, mode, precision),
+                    Num::from_rational(&b, mode, precision),
+                )?,
+                Pair::Complexes(a, b) if b.im == 0.0 && b.re.fract() == 0.0 => {
+                    Complex(a.powi(b.re as i32))
+                }
+                Pair::Complexes(a, b) => Complex(a.powc(b)),
+            };
+            Ok(self.push(v))
+        })
+    }
+
+    /// For functions that only exist for floats. Real results are converted back to the current mode.
+    /// Real arguments outside of the function’s domain, e.g. the logarithm of -1, give complex results.
+    fn float_op<F, G>(&mut self, real: F, complex: G) -> Result<()>
+    where
+        F: Fn(f64) -> f64,
+        G: Fn(Complex64) -> Complex64,
+    {
+        let mode = self.mode;
+        let f = |x: f64| match real(x) {
+            y if y.is_nan() && !x.is_nan() => Complex(complex(Complex64::new(x, 0.0))),
+            y => Value(Num::from_f64(y, mode)),
+        };
+        self.unop(
+            |n| f(n.to_f64()),
+            |r| f(r.to_f64().unwrap_or(f64::NAN)),
+            |c| Complex(complex(c)),
+        )
+    }
+
+    /// Bitwise operations and anything else that only makes sense for integers.
+    fn int_binop<F: FnOnce(BigInt, BigInt) -> Result<BigInt>>(&mut self, f: F) -> Result<()> {
+        pop!("two numbers", self, [a @ number!(), b @ number!()] => {
+            let n = f(a.integer()?, b.integer()?)?;
+            Ok(self.push(Value(Num::from_bigint(n, self.mode))))
+        })
+    }
+
+    /// Whether the top two values are integers without a decimal point, so `2.0 3 /` is still a decimal.
+    fn integers_on_top(&self) -> bool {
+        matches!(
+            self.stack.as_slice(),
+            [.., Value(Num::Decimal(a)), Value(Num::Decimal(b))]
+                if a.fractional_digit_count() <= 0 && b.fractional_digit_count() <= 0
+        )
+    }
+
+    /// Halfway cases are rounded away from zero. Rationals stay rationals, just without a fractional part.
+    fn round_to_integer(&mut self, rounding: RoundingMode) -> Result<()> {
+        pop!("a real number", self, [v @ (Value(_) | Rational(_))] => {
+            let v = match v {
+                Value(n) => Value(n.round_to_integer(rounding)),
+                Rational(r) => Rational(match rounding {
+                    RoundingMode::Floor => r.floor(),
+                    RoundingMode::Ceiling => r.ceil(),
+                    RoundingMode::HalfUp => r.round(),
+                    _ => r.trunc(),
+                }),
+                _ => unreachable!(),
+            };
+            Ok(self.push(v))
         })
     }
 
-    fn binop<F: FnOnce(Num, Num) -> Num>(&mut self, f: F) -> Result<()> {
-        pop!("two numbers", self, [Value(a), Value(b)] => Ok(self.stack.push(Value(f(a, b)))))
+    /// Complex numbers can only be equal or not. Strings are compared alphabetically.
+    fn compare<F: FnOnce(Ordering) -> bool>(&mut self, f: F) -> Result<()> {
+        if let [.., Str(_), Str(_)] = self.stack.as_slice() {
+            let [Str(a), Str(b)] = self.popn()? else {
+                unreachable!()
+            };
+            return Ok(self.push(Value(Num::int(f(a.cmp(&b)).into(), self.mode))));
+        }
+        pop!("two numbers", self, [a @ number!(), b @ number!()] => {
+            let ordering = ordering(a, b)?;
+            Ok(self.push(Value(Num::int(ordering.is_some_and(f).into(), self.mode))))
+        })
+    }
+
+    /// Values as they should be shown to the user, with numbers rounded and in the output radix.
+    /// Scientific and engineering notation are only used for decimal output.
+    fn show(&self, v: &V) -> String {
+        match v {
+            Value(Num::Decimal(d)) if self.dialect == Dialect::Dc && self.output_radix == 10 => {
+                dc::format(d)
+            }
+            Value(n) if self.dialect == Dialect::Dc => {
+                dc::wrap(&n.to_string_radix(self.output_radix))
+            }
+            Value(n) if self.notation == Notation::Plain || self.output_radix != 10 => n
+                .clone()
+                .round(self.precision)
+                .to_string_radix(self.output_radix),
+            Value(n) => n.to_string_notation(self.notation, self.precision),
+            Rational(r) if r.is_integer() => {
+                r.numer().to_str_radix(self.output_radix).to_uppercase()
+            }
+            Rational(r) => format!(
+                "{}/{}",
+                r.numer().to_str_radix(self.output_radix),
+                r.denom().to_str_radix(self.output_radix)
+            )
+            .to_uppercase(),
+            Complex(c) => {
+                let part = |f: f64| {
+                    Num::Float(f)
+                        .round(self.precision)
+                        .to_string_radix(self.output_radix)
+                };
+                let sign = if c.im.is_sign_negative() { '-' } else { '+' };
+                format!("{}{sign}{}j", part(c.re), part(c.im.abs()))
+            }
+            Str(s) => s.clone(),
+            // Strings keep their brackets so the elements stay apart
+            List(items) => format!(
+                "'({})",
+                items
+                    .iter()
+                    .map(|v| match v {
+                        Str(_) => v.to_string(),
+                        v => self.show(v),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            v => v.to_string(),
+        }
+    }
+
+    /// Convert number literals to the current mode and radix, also inside of lists.
+    fn literal(&self, v: V) -> Result<V> {
+        Ok(match v {
+            Literal(s) if self.dialect == Dialect::Dc => {
+                Value(dc::parse_number(&s, self.input_radix, self.mode))
+            }
+            Literal(s) => {
+                if let Some(fraction) = s.strip_suffix('r') {
+                    Rational(parse_rational(fraction, self.input_radix)?)
+                } else if let Some(complex) = s.strip_suffix('j') {
+                    Complex(parse_complex(complex, self.input_radix)?)
+                } else {
+                    Value(Num::parse(&s, self.input_radix, self.mode)?)
+                }
+            }
+            List(items) => List(
+                items
+                    .into_iter()
+                    .map(|v| self.literal(v))
+                    .collect::<Result<_>>()?,
+            ),
+            v => v,
+        })
+    }
+
+    /// Parse a string into code. Unlike with regular input, unparsable tokens are an error.
+    fn quotation(&self, s: &str) -> Result<V> {
+        match self.dialect {
+            Dialect::Dc => Ok(Quotation(dc::parse(s)?)),
+            Dialect::Dcr => match parse(s) {
+                Ok(("", code)) => Ok(Quotation(code)),
+                Ok((rest, _)) => Err(Error::Parse(format!(
+                    "Unparsable tokens in quotation: “{rest}”"
+                ))),
+                Err(e) => Err(Error::Parse(format!("Invalid quotation: {e}"))),
+            },
+        }
+    }
+
+    /// Functions can’t be nested, so a function only sees its own scope and the global one,
+    /// never those of the functions that called it.
+    fn load(&mut self, addr: &V) -> Result<V> {
+        let found = match addr {
+            Identifier(ident) => self.scopes[self.local]
+                .get(ident)
+                .or_else(|| self.scopes[0].get(ident)),
+            Global(ident) => self.scopes[0].get(ident),
+            addr => return Ok(self.reg(addr.clone().int()?)?.clone()),
+        };
+        found.cloned().ok_or_else(|| Error::UnknownIdentifier {
+            ident: addr.to_string(),
+            suggestions: self.similar_names(addr),
+        })
+    }
+
+    /// The names visible to `addr` that are at most a few typos away from it.
+    fn similar_names(&self, addr: &V) -> Vec<String> {
+        let (ident, scopes, prefix) = match addr {
+            Identifier(ident) => (ident, vec![&self.scopes[0], &self.scopes[self.local]], ""),
+            Global(ident) => (ident, vec![&self.scopes[0]], "::"),
+            _ => return Vec::new(),
+        };
+        // Changing every character of a name would make anything with the same length similar.
+        let len = ident.chars().count();
+        let max_distance = (len / 3).max(1).min(len.saturating_sub(1));
+        let mut names: Vec<_> = scopes
+            .into_iter()
+            .flat_map(|scope| scope.keys())
+            .map(|name| (edit_distance(ident, name), name))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .collect();
+        names.sort();
+        // Outside of functions, the innermost scope is the global one, so names can be there twice.
+        names.dedup();
+        names
+            .into_iter()
+            .take(3)
+            .map(|(_, name)| format!("{prefix}{name}"))
+            .collect()
+    }
+
+    fn pop_branch(&mut self) -> Result<V> {
+        pop!("a number and 2 branches", self, [condition @ number!(), a, b] => {
+            Ok(if condition.is_zero() { b } else { a })
+        })
+    }
+
+    fn pop_radix(&mut self) -> Result<u32> {
+        match self.pop()?.int()? {
+            radix @ 2..=36 => Ok(radix as u32),
+            radix => Err(format!("Radix {radix} is not between 2 and 36").into()),
+        }
+    }
+
+    /// For `:pick` and `:roll`, which count from the top of the stack starting at 0.
+    /// Returns the index of the element in the stack.
+    fn pop_depth(&mut self) -> Result<usize> {
+        let depth = self.pop()?.integer()?;
+        match depth.to_usize() {
+            Some(d) if d < self.stack.len() => Ok(self.stack.len() - 1 - d),
+            _ => Err(format!(
+                "Cannot reach element {depth} with {} elements on the stack",
+                self.stack.len()
+            )
+            .into()),
+        }
     }
 
     fn popn<const N: usize>(&mut self) -> Result<[V; N]> {
         // Checking first rather than `pop()?` because we don’t want to pop at all if there aren’t enough values.
         if self.stack.len() < N {
-            return Err(STACK_EMPTY.to_owned());
+            return Err(Error::StackUnderflow {
+                needed: N,
+                available: self.stack.len(),
+            });
         }
-        let mut out = [const { Value(0.0) }; N];
+        self.save_stack(self.stack.len() - N);
+        let mut out = [const { Value(Num::Float(0.0)) }; N];
         for i in (0..N).rev() {
             out[i] = self.stack.pop().unwrap();
         }
@@ -195,10 +1151,159 @@ impl Machine {
         self.stack.push(v);
     }
 
-    fn reg(&mut self, i: usize) -> Result<&mut Num> {
+    fn reg(&mut self, i: usize) -> Result<&mut V> {
         self.registers
             .get_mut(i)
-            .ok_or_else(|| format!("Register {i} out of range"))
+            .ok_or(Error::RegisterOutOfRange(i))
+    }
+}
+
+/// The range for `:substr` and `:slice`, which take a start and a length.
+fn slice(start: V, length: V, len: usize) -> Result<Range<usize>> {
+    let (start, length) = (start.integer()?, length.integer()?);
+    let range = start
+        .to_usize()
+        .zip(length.to_usize())
+        .and_then(|(start, length)| {
+            let end = start.checked_add(length).filter(|&end| end <= len)?;
+            Some(start..end)
+        });
+    match range {
+        Some(range) => Ok(range),
+        None => Err(format!(
+            "{length} elements at {start} are out of bounds for a length of {len}"
+        )
+        .into()),
+    }
+}
+
+/// `start`, `start + step`, … up to but excluding `end`. Negative steps count down.
+/// Like loops, ranges can have at most `max_len` elements.
+fn range(start: Num, end: Num, step: Num, max_len: usize) -> Result<Vec<V>> {
+    let error = |reason: &str| -> Error {
+        format!("Cannot count from {start} to {end} in steps of {step}{reason}").into()
+    };
+    if step.is_zero() || !end.to_f64().is_finite() {
+        return Err(error(""));
+    }
+    let ascending = step.to_f64() > 0.0;
+    let mut items = Vec::new();
+    let mut n = start.clone();
+    while if ascending { n < end } else { n > end } {
+        if items.len() == max_len {
+            return Err(error(&format!(
+                ", that would be more than {max_len} elements"
+            )));
+        }
+        let next = n.clone() + step.clone();
+        // Floats can be too large for the step to change them.
+        if next == n {
+            return Err(error(&format!(", the step is too small for {n}")));
+        }
+        items.push(Value(std::mem::replace(&mut n, next)));
+    }
+    Ok(items)
+}
+
+/// Numbers are ordered by value, strings alphabetically. Complex numbers are only ever equal.
+fn ordering(a: V, b: V) -> Result<Option<Ordering>> {
+    Ok(match (a, b) {
+        (Str(a), Str(b)) => Some(a.cmp(&b)),
+        (a, b) => match Pair::new(a, b)? {
+            Pair::Nums(a, b) => a.partial_cmp(&b),
+            Pair::Rationals(a, b) => a.partial_cmp(&b),
+            Pair::Complexes(a, b) => (a == b).then_some(Ordering::Equal),
+        },
+    })
+}
+
+/// A stable sort by the values that `key` picks, for `:sort` and `:sortby`.
+fn sort<T>(mut items: Vec<T>, key: fn(&T) -> &V) -> Result<Vec<T>> {
+    let mut error = None;
+    items.sort_by(|a, b| {
+        let (a, b) = (key(a), key(b));
+        match ordering(a.clone(), b.clone()) {
+            Ok(Some(ordering)) => ordering,
+            Ok(None) => {
+                error.get_or_insert_with(|| format!("Cannot order {a} and {b}").into());
+                Ordering::Equal
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+                Ordering::Equal
+            }
+        }
+    });
+    error.map_or(Ok(items), Err)
+}
+
+/// How many characters have to be inserted, removed, replaced or swapped with their neighbour
+/// to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<_>, Vec<_>) = (a.chars().collect(), b.chars().collect());
+    // `distances[i][j]` is the distance between the first `i` characters of `a` and the first `j` of `b`.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// Negative amounts shift in the other direction. Shifting right rounds down, like dividing by a power of 2.
+/// Left shifts are limited to `MAX_SHIFT` bits so they can’t use up all memory.
+fn shift(n: BigInt, amount: BigInt) -> Result<BigInt> {
+    if amount.is_negative() {
+        // Everything beyond the length of `n` is shifted out anyway.
+        let bits = amount
+            .magnitude()
+            .to_u64()
+            .unwrap_or(u64::MAX)
+            .min(n.bits() + 1);
+        return Ok(n >> bits);
+    }
+    match amount.to_usize() {
+        Some(bits) if bits <= MAX_SHIFT => Ok(n << bits),
+        _ => Err(format!("Cannot shift by {amount}, the limit is {MAX_SHIFT} bits").into()),
+    }
+}
+
+/// Two numbers converted to the same representation.
+/// Rationals win over decimals because both are exact, floats win over both because they aren’t.
+/// Complex numbers win over everything.
+enum Pair {
+    Nums(Num, Num),
+    Rationals(BigRational, BigRational),
+    Complexes(Complex64, Complex64),
+}
+
+impl Pair {
+    fn new(a: V, b: V) -> Result<Self> {
+        let float = |r: &BigRational| Num::from_rational(r, Mode::Float, Precision::default());
+        Ok(match (a, b) {
+            (a @ Complex(_), b) | (a, b @ Complex(_)) => {
+                Pair::Complexes(a.complex()?, b.complex()?)
+            }
+            (Value(a), Value(b)) => Pair::Nums(a, b),
+            (Value(a @ Num::Float(_)), Rational(b)) => Pair::Nums(a, float(&b)),
+            (Rational(a), Value(b @ Num::Float(_))) => Pair::Nums(float(&a), b),
+            (Value(a), Rational(b)) => Pair::Rationals(a.to_rational()?, b),
+            (Rational(a), Value(b)) => Pair::Rationals(a, b.to_rational()?),
+            (Rational(a), Rational(b)) => Pair::Rationals(a, b),
+            (a, b) => return Err(Error::type_mismatch("two numbers", [&a, &b])),
+        })
     }
 }
 
@@ -208,48 +1313,308 @@ mod tests {
     use crate::parser::parse;
     use test_case::test_case;
 
-    #[test_case("1 2+3-" => vec![Value(0.0)])]
-    #[test_case("3 2 %" => vec![Value(1.0)])]
-    #[test_case("1.5 1 %" => vec![Value(0.5)])]
-    #[test_case("40 2+6/7*" => vec![Value(49.0)])]
-    #[test_case("5 2/3+3" => vec![Value(5.5), Value(3.0)])]
-    #[test_case("2 0s0l" => vec![Value(2.0)]; "storing a number in a register")]
-    #[test_case("10 0s 20 1s c 1l 1l + 0l -" => vec![Value(30.0)])]
-    #[test_case(r"1 1 \+ $" => vec![Value(2.0)]; "delayed application")]
-    #[test_case(r"1 \+ 1 1 + @ $" => vec![Value(3.0)]; "partial application")]
-    #[test_case(r"1 \+ 2 @" => vec![Value(1.0), Curried(Box::new(Add), Box::new(Value(2.0)))])]
-    #[test_case(r"1 2 3 4 S0s \+ S2-r 0l /" => vec![Value(2.5)]; "calculate the average using repeat and stack size")]
-    #[test_case("2 4 > 2 4 ?" => vec![Value(4.0)]; "max()")]
-    #[test_case("2 4 < 2 4 ?" => vec![Value(2.0)]; "min()")]
-    #[test_case("2 2 =" => vec![Value(1.0)]; "equality")]
-    #[test_case("2 4 =" => vec![Value(0.0)]; "inequality")]
-    #[test_case("2 (two) s c (two) l" => vec![Value(2.0)]; "storing a number in a named variable")]
+    #[test_case("1 2+3-" => vec![Value(0.0.into())])]
+    #[test_case("3 2 %" => vec![Value(1.0.into())])]
+    #[test_case("1.5 1 %" => vec![Value(0.5.into())])]
+    #[test_case("40 2+6/7*" => vec![Value(49.0.into())])]
+    #[test_case("5.0 2/3+3" => vec![Value(5.5.into()), Value(3.0.into())])]
+    #[test_case("2 0s0l" => vec![Value(2.0.into())]; "storing a number in a register")]
+    #[test_case("10 0s 20 1s c 1l 1l + 0l -" => vec![Value(30.0.into())])]
+    #[test_case(r"1 1 \+ $" => vec![Value(2.0.into())]; "delayed application")]
+    #[test_case(r"1 \+ 1 1 + @ $" => vec![Value(3.0.into())]; "partial application")]
+    #[test_case(r"1 \+ 2 @" => vec![Value(1.0.into()), Curried(Box::new(Add), Box::new(Value(2.0.into())))])]
+    #[test_case(r"1 2 3 4 S0s \+ S2-r 0l /" => vec![Rational(BigRational::new(5.into(), 2.into()))]; "calculate the average using repeat and stack size")]
+    #[test_case("2 4 > 2 4 ?" => vec![Value(4.0.into())]; "max()")]
+    #[test_case("2 4 < 2 4 ?" => vec![Value(2.0.into())]; "min()")]
+    #[test_case("1 2 / [a] [b] ? 0/3r [a] [b] ? 0j [a] [b] ?" => vec![Str("a".to_owned()), Str("b".to_owned()), Str("b".to_owned())]; "conditions can be any kind of number")]
+    #[test_case("0.1 0.2 + 0.3 =" => vec![Value(1.0.into())]; "exact decimals")]
+    #[test_case("2.0 3 /" => vec![Value(Num::parse("0.66666666666666666666", 10, Mode::Decimal).unwrap())]; "default scale")]
+    #[test_case("-1 k" => panics "Scale -1 is not between 0 and 10000"; "negative scale")]
+    #[test_case("1e30 k" => panics "Scale 1000000000000000000000000000000 is not between 0 and 10000"; "huge scale")]
+    #[test_case("0.5 k" => panics "0.5 is not an integer"; "fractional scale")]
+    #[test_case("10000k K" => vec![Value(10000.0.into())]; "largest scale")]
+    #[test_case("2k 2.0 3 / K" => vec![Value(0.66.into()), Value(2.0.into())]; "setting the scale")]
+    #[test_case("2k 1R 2.0 3 /" => vec![Value(0.67.into())]; "rounding half up")]
+    #[test_case("16i FF 0b11 2i 11" => vec![Value(255.0.into()), Value(3.0.into()), Value(3.0.into())]; "input radix")]
+    #[test_case("20i G Ai 36i Z" => vec![Value(16.0.into()), Value(35.0.into())]; "digits above F")]
+    #[test_case("16i G" => panics "Digit G is too large for base 16"; "digits too large for the radix")]
+    #[test_case("16i I 8o O" => vec![Value(16.0.into()), Value(8.0.into())]; "reading the radix")]
+    #[test_case("1e3 1_000 = 2.5e-1" => vec![Value(1.0.into()), Value(0.25.into())]; "scientific notation")]
+    #[test_case("1/3r 1/6r +" => vec![Rational(BigRational::new(1.into(), 2.into()))]; "adding rationals")]
+    #[test_case("1/3r 3 * 1 =" => vec![Value(1.0.into())]; "rationals are exact")]
+    #[test_case("1/4r 0.5 +" => vec![Rational(BigRational::new(3.into(), 4.into()))]; "decimals become rationals")]
+    #[test_case("1 3 /" => vec![Rational(BigRational::new(1.into(), 3.into()))]; "dividing integers is exact")]
+    #[test_case("6 3 /" => vec![Value(2.0.into())]; "dividing integers without a remainder")]
+    #[test_case("1 0 /" => panics "Division by zero"; "dividing integers by zero")]
+    #[test_case("1 :rat 3 /" => vec![Rational(BigRational::new(1.into(), 3.into()))]; "converting to rational")]
+    #[test_case("7/2r :dec" => vec![Value(3.5.into())]; "converting to decimal")]
+    #[test_case("7/2r 1/1r %" => vec![Rational(BigRational::new(1.into(), 2.into()))]; "rational modulo")]
+    #[test_case("1/2r 1/3r >" => vec![Value(1.0.into())]; "comparing rationals")]
+    #[test_case("3+4j 1-2j +" => vec![Complex(Complex64::new(4.0, 2.0))]; "adding complex numbers")]
+    #[test_case("2j 2j *" => vec![Complex(Complex64::new(-4.0, 0.0))]; "i squared")]
+    #[test_case("3+4j 2 /" => vec![Complex(Complex64::new(1.5, 2.0))]; "mixing complex and real")]
+    #[test_case("3+4j :abs" => vec![Value(5.0.into())]; "absolute value")]
+    #[test_case("-3 :abs 1j :arg 2 *" => vec![Value(3.0.into()), Value(std::f64::consts::PI.into())]; "argument")]
+    #[test_case("3+4j :conj :im 3+4j :re" => vec![Value((-4.0).into()), Value(3.0.into())]; "parts")]
+    #[test_case("3+4j 3+4j = 3+4j 3 =" => vec![Value(1.0.into()), Value(0.0.into())]; "complex equality")]
+    #[test_case("16v 2v" => vec![Value(4.0.into()), Value(Num::parse("1.41421356237309504880", 10, Mode::Decimal).unwrap())]; "square roots use the scale")]
+    #[test_case("-4v" => vec![Complex(Complex64::new(0.0, 2.0))]; "square root of a negative number")]
+    #[test_case("2 10^ 2 -2^ 1.5 2^" => vec![Value(1024.0.into()), Value(0.25.into()), Value(2.25.into())]; "integer powers")]
+    #[test_case("2/3r 2^ 2/3r -1^" => vec![Rational(BigRational::new(4.into(), 9.into())), Rational(BigRational::new(3.into(), 2.into()))]; "rational powers")]
+    #[test_case("4 0.5^ -8 1/3r ^ :im 0 <" => vec![Value(2.0.into()), Value(0.0.into())]; "fractional powers")]
+    #[test_case("0 -1^" => panics "Division by zero"; "negative power of zero")]
+    #[test_case("3 4000000000^" => panics "the result would have more than 16777216 bits"; "huge power")]
+    #[test_case("3/2r 2000000000^" => panics "the result would have more than 16777216 bits"; "huge rational power")]
+    #[test_case("-1 4000000001^" => vec![Value((-1.0).into())]; "huge power of -1")]
+    #[test_case("1 :exp :ln 100 :log" => vec![Value(1.0.into()), Value(2.0.into())]; "logarithms")]
+    #[test_case("-1 :ln :im" => vec![Value(std::f64::consts::PI.into())]; "logarithm of a negative number")]
+    #[test_case("0 :sin 0 :cos 1 :atan 4 *" => vec![Value(0.0.into()), Value(1.0.into()), Value(std::f64::consts::PI.into())]; "trigonometry")]
+    #[test_case(":deg 90 :sin 0.5 :asin :rad 0 :acos" => vec![Value(1.0.into()), Value(30.000000000000004.into()), Value(std::f64::consts::FRAC_PI_2.into())]; "degrees")]
+    #[test_case("5k :pi :e +" => vec![Value(5.85987.into())]; "constants")]
+    #[test_case("200k :pi :e" => vec![
+        Value(Num::parse("3.14159265358979323846264338327950288419716939937510582097494459230781640628620899862803482534211706798214808651328230664709384460955058223172535940812848111745028410270193852110555964462294895493038196", 10, Mode::Decimal).unwrap()),
+        Value(Num::parse("2.71828182845904523536028747135266249775724709369995957496696762772407663035354759457138217852516642742746639193200305992181741359662904357290033429526059563073813232862794349076323382988075319525101901", 10, Mode::Decimal).unwrap()),
+    ]; "constants with a large scale")]
+    #[test_case("12 10 :and 12 10 :or 12 10 :xor" => vec![Value(8.0.into()), Value(14.0.into()), Value(6.0.into())]; "bitwise operators")]
+    #[test_case("0 :not -6 3 :and" => vec![Value((-1.0).into()), Value(2.0.into())]; "two's complement")]
+    #[test_case("1 4 :shl 255 4 :shr -1 1 :shr 1 -1 :shl" => vec![Value(16.0.into()), Value(15.0.into()), Value((-1.0).into()), Value(0.0.into())]; "shifts")]
+    #[test_case("1 1000000000000 :shl" => panics "Cannot shift by 1000000000000"; "shifting too far")]
+    #[test_case("-5 1000000000000 :shr 5 -1000000000000 :shl" => vec![Value((-1.0).into()), Value(0.0.into())]; "shifting everything out")]
+    #[test_case("0xFFFFFFFFFFFFFFFFFF 1 :shl 1 + :popcnt" => vec![Value(73.0.into())]; "popcount of big integers")]
+    #[test_case("-7 2 :div -7 2 %" => vec![Value((-3.0).into()), Value((-1.0).into())]; "integer division truncates")]
+    #[test_case("1.5 1 :and" => panics "1.5 is not an integer"; "bitwise operators reject fractions")]
+    #[test_case("1 0 :div" => panics "Division by zero"; "integer division by zero")]
+    #[test_case("-2.5 :floor -2.5 :ceil -2.5 :trunc -2.5 :round" => vec![Value((-3.0).into()), Value((-2.0).into()), Value((-2.0).into()), Value((-3.0).into())]; "rounding to integers")]
+    #[test_case("7/2r :floor 7/2r :round" => vec![Rational(BigRational::from_integer(3.into())), Rational(BigRational::from_integer(4.into()))]; "rounding rationals")]
+    #[test_case("1 2 d :swap 3 :drop" => vec![Value(1.0.into()), Value(2.0.into()), Value(2.0.into())]; "dup, swap and drop")]
+    #[test_case("1 2 :over 3 :rot" => vec![Value(1.0.into()), Value(1.0.into()), Value(3.0.into()), Value(2.0.into())]; "over and rot")]
+    #[test_case("1 2 3 2 :pick 0 :pick" => vec![Value(1.0.into()), Value(2.0.into()), Value(3.0.into()), Value(1.0.into()), Value(1.0.into())]; "pick")]
+    #[test_case("1 2 3 2 :roll 1 :roll 0 :roll" => vec![Value(2.0.into()), Value(1.0.into()), Value(3.0.into())]; "roll")]
+    #[test_case("1 2 2 :pick" => panics "Cannot reach element 2"; "picking too deep")]
+    #[test_case(":drop" => panics "not enough elements"; "dropping from an empty stack")]
+    #[test_case("[abc] [def] :cat [x = ] 2 :cat" => vec![Str("abcdef".to_owned()), Str("x = 2".to_owned())]; "concatenation")]
+    #[test_case("[äbc] :len [] :len" => vec![Value(3.0.into()), Value(0.0.into())]; "string length")]
+    #[test_case("[hello world] 6 5 :substr" => vec![Str("world".to_owned())]; "substring")]
+    #[test_case("[hello] 3 5 :substr" => panics "out of bounds"; "substring out of bounds")]
+    #[test_case("[ab] 18446744073709551615 1 :substr" => panics "out of bounds"; "substring end overflows")]
+    #[test_case("'(1 [a] '(2))" => vec![List(vec![Value(1.0.into()), Str("a".to_owned()), List(vec![Value(2.0.into())])])]; "list literal")]
+    #[test_case("16i '(A 10)" => vec![List(vec![Value(10.0.into()), Value(16.0.into())])]; "list elements use the input radix")]
+    #[test_case("1 2 3 2 :pack" => vec![Value(1.0.into()), List(vec![Value(2.0.into()), Value(3.0.into())])]; "pack")]
+    #[test_case("0 :pack :len" => vec![Value(0.0.into())]; "packing nothing")]
+    #[test_case("1 2 2 :pack :unpack" => vec![Value(1.0.into()), Value(2.0.into()), Value(2.0.into())]; "unpack")]
+    #[test_case("1 2 3 :pack" => panics "needed 3 but there were 2"; "packing too many")]
+    #[test_case("'(5 6 7) d 0 :get :swap -1 :get" => vec![Value(5.0.into()), Value(7.0.into())]; "indexing")]
+    #[test_case("'(5 6 7) 3 :get" => panics "out of bounds"; "index out of bounds")]
+    #[test_case("'(5 6 7) -4 :get" => panics "out of bounds"; "negative index out of bounds")]
+    #[test_case("'(5 6 7) -9223372036854775808 :get" => panics "out of bounds"; "most negative index")]
+    #[test_case("'(5 6 7) 99999999999999999999 :get" => panics "out of bounds"; "huge index")]
+    #[test_case("'(1) 2 :append '(3) :cat :len" => vec![Value(3.0.into())]; "append and concatenation")]
+    #[test_case("'(1 2 3 4) 1 2 :slice" => vec![List(vec![Value(2.0.into()), Value(3.0.into())])]; "slice")]
+    #[test_case("'(1) 18446744073709551615 1 :slice" => panics "out of bounds"; "slice end overflows")]
+    #[test_case(r"1 '(4 5 6) :unpack 1 - \+ :swap r" => vec![Value(1.0.into()), Value(15.0.into())]; "aggregating part of the stack")]
+    #[test_case(r"'(1 2 3) \*2@ :map" => vec![List(vec![Value(2.0.into()), Value(4.0.into()), Value(6.0.into())])]; "map")]
+    #[test_case(r"5 '(1 2 3) { *2 +1 } :map" => vec![Value(5.0.into()), List(vec![Value(3.0.into()), Value(5.0.into()), Value(7.0.into())])]; "mapping a composed function")]
+    #[test_case(r"'(1 2 3 4) \>2@ :filter" => vec![List(vec![Value(3.0.into()), Value(4.0.into())])]; "filter")]
+    #[test_case(r"'([a] [b]) \:len :filter" => vec![List(vec![Str("a".to_owned()), Str("b".to_owned())])]; "filtering with a named operator")]
+    #[test_case("'(1 2) {:drop 1/2r} :filter" => vec![List(vec![Value(1.0.into()), Value(2.0.into())])]; "filtering by rationals")]
+    #[test_case("'(1) [:drop [a]] :quote :filter" => panics "Expected a number from the filter"; "filter needs numbers")]
+    #[test_case(r"'(1 2) \d :map" => panics "the stack size changed by +2 instead of +1"; "functions have to leave one value")]
+    #[test_case(r"1 '(2 3) \+ :map" => panics "the stack size changed by +0 instead of +1"; "functions can't take more than their arguments")]
+    #[test_case(r"'(1 2 3 4) 0 \+ :fold '() 1 \* :fold" => vec![Value(10.0.into()), Value(1.0.into())]; "fold")]
+    #[test_case(r"'([b] [c]) [a] \:cat :fold" => vec![Str("abc".to_owned())]; "fold is left to right")]
+    #[test_case(r"'(1 2 3) 0 \+ :scan" => vec![List(vec![Value(1.0.into()), Value(3.0.into()), Value(6.0.into())])]; "scan")]
+    #[test_case("'(1 2 3) '([a] [b]) :zip" => vec![List(vec![
+        List(vec![Value(1.0.into()), Str("a".to_owned())]),
+        List(vec![Value(2.0.into()), Str("b".to_owned())]),
+    ])]; "zip stops at the shorter list")]
+    #[test_case("'(3 1/2r 2 -1) :sort '([b] [a]) :sort" => vec![
+        List(vec![Value((-1.0).into()), Rational(BigRational::new(1.into(), 2.into())), Value(2.0.into()), Value(3.0.into())]),
+        List(vec![Str("a".to_owned()), Str("b".to_owned())]),
+    ]; "sort")]
+    #[test_case("'(1 [a]) :sort" => panics "Expected two numbers"; "sorting mixed values")]
+    #[test_case(r"'([ccc] [a] [bb] [d]) \:len :sortby" => vec![List(vec![Str("a".to_owned()), Str("d".to_owned()), Str("bb".to_owned()), Str("ccc".to_owned())])]; "sort by is stable")]
+    #[test_case("2 5 :range 1 1 :range" => vec![List(vec![Value(2.0.into()), Value(3.0.into()), Value(4.0.into())]), List(vec![])]; "range")]
+    #[test_case("0 {d <5} {+1} :while" => vec![Value(5.0.into())]; "while loop")]
+    #[test_case("48 18 {d =0} {:swap :over %} :until :drop" => vec![Value(6.0.into())]; "gcd")]
+    #[test_case("0 1234 {d >0} [:swap :over 10 % + :swap 10 :div] :quote :while :drop" => vec![Value(10.0.into())]; "digit sum with a quotation")]
+    #[test_case("2.0 [d d * 2 - :abs 0.0001 >] :quote [d 2 :swap / + 2 /] :quote :while 1000 * :round" => vec![Value(1414.0.into())]; "newton iteration")]
+    #[test_case(r"{ d =0 } (isZero)s 3 (isZero) \-1@ :until" => vec![Value(0.0.into())]; "identifiers in loops")]
+    #[test_case("5 :maxiter 0 {d <5} {+1} :while" => vec![Value(5.0.into())]; "loop right at the limit")]
+    #[test_case("4 :maxiter 0 {d <5} {+1} :while" => panics "didn’t finish within 4 iterations"; "loop over the limit")]
+    #[test_case("3/2r {d} [1/2r -] :quote :while" => vec![Rational(BigRational::zero())]; "rational loop condition")]
+    #[test_case(r"[x] \d \d :while" => panics "Expected a number from the loop condition"; "loop condition must be a number")]
+    #[test_case(r"1 2 1 \+ \- :if 1 2 0 \+ \- :if" => vec![Value(3.0.into()), Value((-1.0).into())]; "if applies functions")]
+    #[test_case(r"5 1 {*2} {/0} :if" => vec![Value(10.0.into())]; "only the chosen branch of if is evaluated")]
+    #[test_case("[d 1 > [d 1 - (fact)$ *] :quote [:drop 1] :quote :if] :quote (fact)s 5 (fact)$ 0 (fact)$" => vec![Value(120.0.into()), Value(1.0.into())]; "factorial")]
+    #[test_case(r"[a] \+ \- :if" => panics "Expected a number and 2 branches"; "if needs a number")]
+    #[test_case(r"\+ 0@ (done)s \- 1@ (countdown) | (step)s \d \>0@ | \:if (done)@ (step)@ | (countdown)s 10000 (countdown)$" => vec![Value(0.0.into())]; "deep recursion of composed functions")]
+    #[test_case("[d 0 > [1 - (count)$] :quote [] :quote ?] :quote (count)s 10000 (count)$" => vec![Value(0.0.into())]; "deep recursion of quotations")]
+    #[test_case(r"\*2@ (double) | (quadruple)s \*2@ (double)s 3 (quadruple)$" => vec![Value(12.0.into())]; "identifiers are resolved when they are applied")]
+    #[test_case(r"1 2 \+ (nope) | $" => panics "nope not found"; "composing an unknown identifier")]
+    #[test_case("{a b -> (b) (a) -}(rsub)s 1 5 (rsub)$" => vec![Value(4.0.into())]; "named parameters")]
+    #[test_case("{x -> (x) (x) *}(sq)s {a b -> (a) (sq) | (b) (sq) | +}(sumsq)s 3 4 (sumsq)$" => vec![Value(25.0.into())]; "nested calls have their own parameters")]
+    #[test_case("5 (x)s {x -> (x) *2}(double)s 3 (double)$ (x)l" => vec![Value(6.0.into()), Value(5.0.into())]; "parameters shadow variables")]
+    #[test_case("{x -> (x) *2}(double)s 3 (double)$ (x)l" => panics "x not found"; "parameters are discarded after the call")]
+    #[test_case("1 2 (mni)$" => panics "mni not found, did you mean min?"; "suggestions")]
+    #[test_case("1 (ab)s 2 (ac)s 3 (abc)s (aa)l" => panics "aa not found, did you mean ab or ac?"; "several suggestions")]
+    #[test_case("{count -> (cuont)l}(f)s 1 (f)$" => panics "cuont not found, did you mean count?"; "suggested parameters")]
+    #[test_case("1 (count)s (::cuont)l" => panics "::cuont not found, did you mean ::count?"; "suggested globals")]
+    #[test_case("{a b -> (a)}(first)s 1 (first)$" => panics "not enough elements"; "too few arguments")]
+    #[test_case("{x -> (x) *2 s(y)@ l(y)@ l(y)@ +}(f)s 3 (f)$" => vec![Value(12.0.into())]; "local definitions")]
+    #[test_case("{x -> (x) s(y)@}(f)s 3 (f)$ (y)l" => panics "y not found"; "local definitions are discarded")]
+    #[test_case("1 (y)s {x -> (x) s(y)@ l(y)@}(f)s 5 (f)$ (y)l" => vec![Value(5.0.into()), Value(1.0.into())]; "local definitions shadow global ones")]
+    #[test_case("{ l(b)@ }(f)s {b -> (b) l(f)@}(g)s 1 (g)$" => panics "b not found"; "functions without parameters don't see the scope of their caller")]
+    #[test_case("{ s(x)@ }(f)s 5 (f)$ (x)l" => panics "x not found"; "functions without parameters have local definitions")]
+    #[test_case(r"\+ 0@ (done)s \- 1@ (countdown) | (step)s \d \>0@ | \:if (done)@ (step)@ | (body)s {n -> (n) (body) |}(countdown)s 10000 (countdown)$" => vec![Value(0.0.into())]; "deep recursion of functions with parameters")]
+    #[test_case("{x -> (x) s(::y)@}(f)s 5 (f)$ (y)l" => vec![Value(5.0.into())]; "defining a global variable in a function")]
+    #[test_case("1 (y)s {y -> l(::y)@ (y) +}(f)s 5 (f)$" => vec![Value(6.0.into())]; "reading a shadowed global variable")]
+    #[test_case("{a -> l(b)@}(f)s {b -> (b) l(f)@}(g)s 1 (g)$" => panics "b not found"; "functions don't see the scope of their caller")]
+    #[test_case(r"\l(b)@ (f)s {b -> l(f)@}(g)s 7 (g)$" => panics "b not found"; "curried functions don't see the scope of their caller")]
+    #[test_case(r"\s(x)@ (setx)s {a -> (a) (setx) |}(g)s 5 (g)$ (x)l" => vec![Value(5.0.into())]; "curried functions define global variables")]
+    #[test_case("[(b)l] :quote (f)s {b -> (f)$}(g)s 7 (g)$" => panics "b not found"; "quotations from variables don't see the scope of their caller")]
+    #[test_case(r"1 0 \/ \:drop :try" => vec![Value(1.0.into()), Value(0.0.into())]; "the stack is reset on errors")]
+    #[test_case(r"1 0 \/ \:cat [!]@ :try" => vec![Value(1.0.into()), Value(0.0.into()), Str("Division by zero!".to_owned())]; "the handler gets the message")]
+    #[test_case(r"4 2 \/ \d :try" => vec![Value(2.0.into())]; "the handler only runs on errors")]
+    #[test_case(r"[oops] \:throw \:cat [!]@ :try" => vec![Str("oops".to_owned()), Str("oops!".to_owned())]; "throw")]
+    #[test_case("-1 :throw" => panics "-1"; "throwing a number")]
+    #[test_case(r"\:throw [inner]@ \:throw :try" => panics "inner"; "rethrowing")]
+    #[test_case(r"{x -> (x) :throw}(f)s 5 (f) \:drop :try (x)l" => panics "x not found"; "the scope is left on errors")]
+    #[test_case("[a] [b] < [a] [a] =" => vec![Value(1.0.into()), Value(1.0.into())]; "comparing strings")]
+    #[test_case("[text] 3s [label] (name)s 3l (name)l" => vec![Str("text".to_owned()), Str("label".to_owned())]; "storing strings")]
+    #[test_case("[1 2 +] x" => vec![Value(3.0.into())]; "executing a string")]
+    #[test_case("[2 *] :quote 3 :over x :swap x" => vec![Value(12.0.into())]; "executing a quotation twice")]
+    #[test_case("[1 2 +] :quote" => vec![Quotation(vec![Literal("1".to_owned()), Literal("2".to_owned()), Add])]; "quoting a string")]
+    #[test_case("1 [d 2 *] :quote 4 r" => vec![Value(1.0.into()), Value(2.0.into()), Value(4.0.into()), Value(8.0.into()), Value(16.0.into())]; "repeating a quotation")]
+    #[test_case("1 [5] :quote [1 0 /] :quote ?" => vec![Value(5.0.into())]; "only the chosen branch is executed")]
+    #[test_case("[d 1 - d 0 > [(loop)l x] :quote [] :quote ?] (loop)s 3 (loop)l x" => vec![Value(3.0.into()), Value(2.0.into()), Value(1.0.into()), Value(0.0.into())]; "recursion")]
+    #[test_case("[1 )] x" => panics "Unparsable tokens"; "invalid quotation")]
+    #[test_case("2 2 =" => vec![Value(1.0.into())]; "equality")]
+    #[test_case("2 4 =" => vec![Value(0.0.into())]; "inequality")]
+    #[test_case("2 (two) s c (two) l" => vec![Value(2.0.into())]; "storing a number in a named variable")]
     #[test_case(r"\- (minus) s (minus) l" => vec![Fun(Box::new(Sub))]; "storing a function in a named variable")]
-    #[test_case(r"\- (minus) s 2 1 (minus) $" => vec![Value(1.0)]; "applying a function from a named variable")]
-    #[test_case(r"\? -1@ 1@ (positiveIfTrue)s 5 (positiveIfTrue)$" => vec![Value(1.0)]; "curried ternary operator")]
-    #[test_case(r"\+1@\*2@| (plus1Times2)s 4 (plus1Times2)$" => vec![Value(10.0)]; "composed functions")]
-    #[test_case(r"{ +1*2 } (plus1Times2)s 4 (plus1Times2)$" => vec![Value(10.0)]; "composed functions using function mode")]
-    #[test_case(r"\s 256@ \s257@ | \l257@ | \l256@ | \< | \l257@ | \l256@ | \? | (min)s 2 4 (min)$ 4 3 (min)$" => vec![Value(2.0), Value(3.0)]; "min() implementation")]
-    #[test_case(r"{ s256 s257 l257 l256 < l257 l256 ? }(min)s  2 4 (min)$ 4 3 (min)$" => vec![Value(2.0), Value(3.0)]; "min() implementation using function mode")]
-    #[test_case(r"{*2}" => vec![Curried(Box::new(Mul), Box::new(Value(2.0)))]; "curry in function mode")]
-    #[test_case(r"{?\+@\-@}" => vec![
-        Curried(
+    #[test_case(r"\- (minus) s 2 1 (minus) $" => vec![Value(1.0.into())]; "applying a function from a named variable")]
+    #[test_case(r"\? -1@ 1@ (positiveIfTrue)s 5 (positiveIfTrue)$" => vec![Value(1.0.into())]; "curried ternary operator")]
+    #[test_case(r"\+1@\*2@| (plus1Times2)s 4 (plus1Times2)$" => vec![Value(10.0.into())]; "composed functions")]
+    #[test_case(r"{ +1*2 } (plus1Times2)s 4 (plus1Times2)$" => vec![Value(10.0.into())]; "composed functions using function mode")]
+    #[test_case(r"\s 256@ \s257@ | \l257@ | \l256@ | \< | \l257@ | \l256@ | \? | (min)s 2 4 (min)$ 4 3 (min)$" => vec![Value(2.0.into()), Value(3.0.into())]; "min() implementation")]
+    #[test_case(r"{ s256 s257 l257 l256 < l257 l256 ? }(min)s  2 4 (min)$ 4 3 (min)$" => vec![Value(2.0.into()), Value(3.0.into())]; "min() implementation using function mode")]
+    #[test_case(r"{*2}" => vec![Lambda(vec![], Box::new(Curried(Box::new(Mul), Box::new(Value(2.0.into())))))]; "curry in function mode")]
+    #[test_case(r"{?\+@\-@}" => vec![Lambda(
+        vec![],
+        Box::new(Curried(
             Box::new(Curried(
                 Box::new(Conditional),
                 Box::new(Fun(Box::new(Add))),
             )),
             Box::new(Fun(Box::new(Sub))),
-        )
-    ]; "applying partials in function mode")]
+        )),
+    )]; "applying partials in function mode")]
     fn evaluation(raw: &str) -> Vec<V> {
         let input = parse(raw).expect("parsing failed").1;
         dbg!(raw, &input);
-        let mut machine = Machine::new();
+        let mut machine = Machine::new(Mode::Decimal, Dialect::Dcr);
         for v in input {
             dbg!(&v);
-            machine.process(v).expect("processing failed");
+            machine
+                .process(v)
+                .unwrap_or_else(|e| panic!("processing failed: {e}"));
             dbg!(&machine.stack);
         }
         machine.stack
     }
+
+    /// Like `evaluation`, but errors are ignored so the state afterwards can be checked.
+    #[test_case(r"1 [a] 2 3 \+ \+ | $" => vec![
+        Value(1.0.into()),
+        Str("a".to_owned()),
+        Value(2.0.into()),
+        Value(3.0.into()),
+        Composed(Box::new(Fun(Box::new(Add))), Box::new(Fun(Box::new(Add)))),
+    ]; "composed functions")]
+    #[test_case("5 0s [7 0s 1 [a] +] x 0l" => vec![Str("7 0s 1 [a] +".to_owned()), Value(5.0.into())]; "registers")]
+    #[test_case("1 (y)s [2 (y)s 3 (z)s 1 0 /] x (y)l (z)l" => vec![Str("2 (y)s 3 (z)s 1 0 /".to_owned()), Value(1.0.into()), Identifier("z".to_owned())]; "variables")]
+    #[test_case("1 2 [c 1 0 /] x" => vec![Value(1.0.into()), Value(2.0.into()), Str("c 1 0 /".to_owned())]; "clearing")]
+    #[test_case("1 2 3 [2 :roll 0 /] x" => vec![Value(1.0.into()), Value(2.0.into()), Value(3.0.into()), Str("2 :roll 0 /".to_owned())]; "rolling")]
+    #[test_case("1 2 3 [3 :pack 0 :get 0 /] x" => vec![Value(1.0.into()), Value(2.0.into()), Value(3.0.into()), Str("3 :pack 0 :get 0 /".to_owned())]; "packing")]
+    #[test_case("1 2 [+ 0s 0l 1 +] x 0l" => vec![Value(4.0.into()), Value(3.0.into())]; "successful operations are kept")]
+    fn rollback(raw: &str) -> Vec<V> {
+        let mut machine = Machine::new(Mode::Decimal, Dialect::Dcr);
+        for v in parse(raw).expect("parsing failed").1 {
+            let _ = machine.process(v);
+        }
+        machine.stack
+    }
+
+    /// Adding 1 doesn’t change a float this large, so the range would never end.
+    #[test]
+    fn float_ranges_that_dont_advance() {
+        let mut machine = Machine::new(Mode::Float, Dialect::Dcr);
+        let error = parse("1e16 1e16 10 + 1 :rangeby")
+            .expect("parsing failed")
+            .1
+            .into_iter()
+            .find_map(|v| machine.process(v).err())
+            .expect("processing succeeded");
+        assert!(
+            error.to_string().contains("the step is too small"),
+            "{error}"
+        );
+    }
+
+    /// Runs on a thread with the same stack as the interpreter, which the limit is meant for.
+    #[test_case("[d 1 > [d 1 - (fact)$ *] :quote [:drop 1] :quote :if] :quote (fact)s 100000 (fact)$"; "factorial")]
+    #[test_case(r"(g) \+ | (g)s 1 (g)$"; "composition")]
+    #[test_case("[d 0 > [1 - 1 :pack (g) :map 0 :get] :quote [] :quote :if] :quote (g)s 100000 (g)$"; "list operations")]
+    fn recursion_too_deep(raw: &'static str) {
+        let error = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| error_message(raw))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(
+            error.contains("Recursion is deeper than 10000 calls"),
+            "{error}"
+        );
+    }
+
+    #[test_case("min", "min" => 0; "same")]
+    #[test_case("min", "max" => 2; "replaced")]
+    #[test_case("min", "mni" => 1; "swapped")]
+    #[test_case("avg", "avgStep" => 4; "inserted")]
+    #[test_case("", "ä" => 1; "characters")]
+    fn edit_distance(a: &str, b: &str) -> usize {
+        super::edit_distance(a, b)
+    }
+
+    #[test_case("1 (a)s (b)l" => "b not found"; "no suggestions for single characters")]
+    #[test_case("1 (ab)s (ba)l" => "ba not found, did you mean ab?"; "swapped characters")]
+    fn error_message(raw: &str) -> String {
+        let mut machine = Machine::new(Mode::Decimal, Dialect::Dcr);
+        parse(raw)
+            .expect("parsing failed")
+            .1
+            .into_iter()
+            .find_map(|v| machine.process(v).err())
+            .expect("processing succeeded")
+            .to_string()
+    }
+
+    #[test_case("1 [a] +" => vec!["+"]; "operation")]
+    #[test_case(r"\+ (f)s 1 [a] (f)$" => vec!["+", "f"]; "identifier")]
+    #[test_case(r"\+ \- | (g)s 1 2 [a] (g)$" => vec!["+", "g"]; "composed functions are part of their identifier")]
+    #[test_case(r"\+[a]@ (h)s 1 (h)$" => vec!["+", "h"]; "curried functions are part of their identifier")]
+    #[test_case("[a] 1 (avg)$" => vec!["+", "avgStep", "avg"]; "stdlib functions")]
+    #[test_case(r"\+ (f)s (f) \*2@ | (g)s [a] 1 (g)$" => vec!["+", "f", "g"]; "non-tail calls")]
+    #[test_case("[d 0 > [1 - (count)$] :quote [[a] +] :quote ?] :quote (count)s 1000 (count)$" => vec!["+", "count"]; "tail calls")]
+    #[test_case(r"1 [a] \+ \:drop :try 1 0 /" => vec!["/"]; "caught errors are forgotten")]
+    fn trace(raw: &str) -> Vec<String> {
+        let mut machine = Machine::new(Mode::Decimal, Dialect::Dcr);
+        for v in parse(raw).expect("parsing failed").1 {
+            if machine.process(v).is_err() {
+                return machine.trace().map(|v| v.to_string()).collect();
+            }
+        }
+        panic!("processing succeeded")
+    }
 }
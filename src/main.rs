@@ -1,32 +1,110 @@
-use machine::Machine;
-use parser::parse;
+use bigdecimal::num_bigint::BigInt;
+use dc::Dialect;
+use error::{Error, Located};
+use machine::{Machine, STACK_SIZE};
+use num::{Mode, Notation, Num, Precision};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use parser::parse_with_offsets;
 use std::{fmt, io::stdin};
 
+mod dc;
+mod error;
 mod machine;
+mod num;
 mod parser;
 mod stdlib;
 
-/// Number type of the machine
-type Num = f64;
-
-type Result<T> = std::result::Result<T, String>;
+type Result<T> = std::result::Result<T, Error>;
 
 fn main() {
-    let mut machine = Machine::new();
-    for line in stdin().lines().map_while(|l| l.ok()) {
-        match parse(&line) {
-            Ok(("", values)) => {
-                for v in values {
-                    if let Err(e) = machine.process(v.clone()) {
-                        eprintln!("Error at {v}: {e}, stack was:\n");
-                        machine.process(V::Printall).unwrap();
-                        break;
-                    }
+    let mut mode = Mode::default();
+    let mut dialect = Dialect::default();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--float" => mode = Mode::Float,
+            "--dc" => dialect = Dialect::Dc,
+            _ => {
+                eprintln!("Unknown argument “{arg}”");
+                std::process::exit(1);
+            }
+        }
+    }
+    // Tail calls run in a loop, but everything else, e.g. `dc` loops that do something after the
+    // recursive call, still nests, and needs a lot more stack than the main thread has.
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(mode, dialect))
+        .expect("Could not start the interpreter thread")
+        .join()
+        .unwrap();
+}
+
+fn run(mode: Mode, dialect: Dialect) {
+    let mut machine = Machine::new(mode, dialect);
+    let mut pending = String::new();
+    let mut first_line = 1;
+    for (i, line) in stdin().lines().map_while(|l| l.ok()).enumerate() {
+        let (source, parsed) = match dialect {
+            Dialect::Dcr => {
+                first_line = i + 1;
+                let parsed = match parse_with_offsets(&line) {
+                    Ok(("", values)) => Ok(values),
+                    // Should the valid parts still be executed?
+                    Ok((rest, _)) => Err((
+                        line.len() - rest.len(),
+                        Error::Parse(format!("Unparsable tokens “{rest}”")),
+                    )),
+                    Err(_) => unreachable!(), // it actually is!
+                };
+                (line, parsed)
+            }
+            Dialect::Dc => {
+                if pending.is_empty() {
+                    first_line = i + 1;
                 }
+                pending.push_str(&line);
+                pending.push('\n');
+                if dc::is_incomplete(&pending) {
+                    continue;
+                }
+                let source = std::mem::take(&mut pending);
+                let parsed = dc::parse_with_offsets(&source);
+                (source, parsed)
+            }
+        };
+        let values = match parsed {
+            Ok(values) => values,
+            Err((offset, e)) => {
+                eprintln!("{}", Located::new(e, &source, offset, first_line));
+                continue;
+            }
+        };
+        for (offset, v) in values {
+            if let Err(e) = machine.process(v) {
+                eprintln!("{}", Located::new(e, &source, offset, first_line));
+                print_trace(&machine);
+                eprintln!("stack was:\n");
+                machine.process(V::Printall).unwrap();
+                break;
             }
-            // Should the valid parts still be executed?
-            Ok((rest, _)) => eprintln!("Input contained unparsable tokens: “{rest}”"),
-            Err(_) => unreachable!(), // it actually is!
+        }
+    }
+}
+
+/// Recursion that went too deep would list the same function thousands of times,
+/// so repetitions are counted instead.
+fn print_trace(machine: &Machine) {
+    let mut trace = machine.trace().peekable();
+    while let Some(call) = trace.next() {
+        let mut times = 1;
+        while trace.next_if_eq(&call).is_some() {
+            times += 1;
+        }
+        match times {
+            1 => eprintln!("  in {call}"),
+            _ => eprintln!("  in {call} ({times} times)"),
         }
     }
 }
@@ -39,15 +117,103 @@ enum V {
     Mul,
     Div,
     Mod,
+    ToDecimal,
+    ToRational,
+    // Complex numbers
+    Abs,
+    Arg,
+    Conj,
+    Re,
+    Im,
+    // Scientific functions
+    Sqrt,
+    Pow,
+    Ln,
+    Log,
+    Exp,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Pi,
+    Euler,
+    Degrees,
+    Radians,
+    // Integers
+    And,
+    Or,
+    Xor,
+    Not,
+    ShiftLeft,
+    ShiftRight,
+    Popcount,
+    IntDiv,
+    Floor,
+    Ceil,
+    Trunc,
+    Round,
     // User interaction
     Print,
+    PrintRaw,
     Printall,
     Quit,
+    // Precision
+    SetScale,
+    GetScale,
+    SetRounding,
+    SetInputRadix,
+    GetInputRadix,
+    SetOutputRadix,
+    GetOutputRadix,
+    SetNotation,
     // Stack manipulation
     Value(Num),
+    // A number as written in the input, converted by the machine when it’s pushed.
+    Literal(String),
+    Rational(BigRational),
+    Complex(Complex64),
+    Str(String),
+    Concat,
+    Length,
+    Substring,
+    List(Vec<V>),
+    Pack,
+    Unpack,
+    Get,
+    Append,
+    Slice,
+    // Take a function that is applied to the elements
+    Map,
+    Filter,
+    Fold,
+    Scan,
+    SortBy,
+    Zip,
+    Sort,
+    Range,
+    RangeBy,
+    Linspace,
+    // Parsed code that runs when it is applied
+    Quotation(Vec<V>),
+    Quote,
+    Execute,
     Stacksize,
     Clear,
+    Dup,
+    Swap,
+    Drop,
+    Over,
+    Rot,
+    Pick,
+    Roll,
     Repeat,
+    While,
+    Until,
+    SetMaxIterations,
+    Try,
+    Throw,
     Store,
     Load,
     // Partial application and function references
@@ -57,6 +223,12 @@ enum V {
     // (Fn, Arg)
     Curried(Box<V>, Box<V>),
     Identifier(String),
+    // An identifier that always refers to the global variable, even within a function
+    Global(String),
+    // Turns the function on the stack into a `Lambda` with these parameters, which may be none
+    Bind(Vec<String>),
+    // (Parameters, Fn), applied with the parameters bound to values from the stack
+    Lambda(Vec<String>, Box<V>),
     Compose,
     // (Fn, Fn), executed left to right
     Composed(Box<V>, Box<V>),
@@ -65,18 +237,47 @@ enum V {
     GreaterThan,
     Equal,
     Conditional,
+    If,
 }
 
 impl V {
     fn number(self) -> Result<Num> {
         match self {
             V::Value(v) => Ok(v),
-            _ => Err(format!("Expected numeric value, got {self:?}")),
+            _ => Err(Error::type_mismatch("numeric value", [&self])),
         }
     }
 
     fn int(self) -> Result<usize> {
-        Ok(self.number()?.round() as usize)
+        Ok(self.number()?.to_f64().round() as usize)
+    }
+
+    /// Like `int`, but values with a fractional part are an error instead of being rounded.
+    fn integer(self) -> Result<BigInt> {
+        match self {
+            V::Value(n) => n.to_bigint(),
+            V::Rational(r) if r.is_integer() => Ok(r.to_integer()),
+            _ => Err(Error::type_mismatch("an integer", [&self])),
+        }
+    }
+
+    /// Conditions can be any kind of number, and only zero is false.
+    fn is_zero(&self) -> bool {
+        match self {
+            V::Value(n) => n.is_zero(),
+            V::Rational(r) => r.is_zero(),
+            V::Complex(c) => c.is_zero(),
+            _ => false,
+        }
+    }
+
+    fn complex(self) -> Result<Complex64> {
+        match self {
+            V::Complex(c) => Ok(c),
+            V::Value(n) => Ok(Complex64::new(n.to_f64(), 0.0)),
+            V::Rational(r) => Ok(Complex64::new(r.to_f64().unwrap_or(f64::NAN), 0.0)),
+            _ => Err(Error::type_mismatch("numeric value", [&self])),
+        }
     }
 }
 
@@ -90,17 +291,111 @@ impl fmt::Display for V {
             Div => write!(f, "/"),
             Mod => write!(f, "%"),
             Value(num) => write!(f, "{num}"),
+            Literal(s) => write!(f, "{s}"),
+            Rational(r) => write!(f, "{r}"),
+            ToDecimal => write!(f, ":dec"),
+            ToRational => write!(f, ":rat"),
+            Complex(c) => write!(f, "{}{:+}j", c.re, c.im),
+            Str(s) => write!(f, "[{s}]"),
+            Concat => write!(f, ":cat"),
+            Length => write!(f, ":len"),
+            Substring => write!(f, ":substr"),
+            Quotation(code) => write!(
+                f,
+                "[{}]",
+                code.iter().map(V::to_string).collect::<Vec<_>>().join(" ")
+            ),
+            Quote => write!(f, ":quote"),
+            List(items) => write!(
+                f,
+                "'({})",
+                items.iter().map(V::to_string).collect::<Vec<_>>().join(" ")
+            ),
+            Pack => write!(f, ":pack"),
+            Unpack => write!(f, ":unpack"),
+            Get => write!(f, ":get"),
+            Append => write!(f, ":append"),
+            Slice => write!(f, ":slice"),
+            Map => write!(f, ":map"),
+            Filter => write!(f, ":filter"),
+            Fold => write!(f, ":fold"),
+            Scan => write!(f, ":scan"),
+            SortBy => write!(f, ":sortby"),
+            Zip => write!(f, ":zip"),
+            Sort => write!(f, ":sort"),
+            Range => write!(f, ":range"),
+            RangeBy => write!(f, ":rangeby"),
+            Linspace => write!(f, ":linspace"),
+            Execute => write!(f, "x"),
+            PrintRaw => write!(f, "P"),
+            Abs => write!(f, ":abs"),
+            Arg => write!(f, ":arg"),
+            Conj => write!(f, ":conj"),
+            Re => write!(f, ":re"),
+            Im => write!(f, ":im"),
+            Sqrt => write!(f, "v"),
+            Pow => write!(f, "^"),
+            Ln => write!(f, ":ln"),
+            Log => write!(f, ":log"),
+            Exp => write!(f, ":exp"),
+            Sin => write!(f, ":sin"),
+            Cos => write!(f, ":cos"),
+            Tan => write!(f, ":tan"),
+            Asin => write!(f, ":asin"),
+            Acos => write!(f, ":acos"),
+            Atan => write!(f, ":atan"),
+            Pi => write!(f, ":pi"),
+            Euler => write!(f, ":e"),
+            Degrees => write!(f, ":deg"),
+            Radians => write!(f, ":rad"),
+            And => write!(f, ":and"),
+            Or => write!(f, ":or"),
+            Xor => write!(f, ":xor"),
+            Not => write!(f, ":not"),
+            ShiftLeft => write!(f, ":shl"),
+            ShiftRight => write!(f, ":shr"),
+            Popcount => write!(f, ":popcnt"),
+            IntDiv => write!(f, ":div"),
+            Floor => write!(f, ":floor"),
+            Ceil => write!(f, ":ceil"),
+            Trunc => write!(f, ":trunc"),
+            Round => write!(f, ":round"),
+            Dup => write!(f, "d"),
+            Swap => write!(f, ":swap"),
+            Drop => write!(f, ":drop"),
+            Over => write!(f, ":over"),
+            Rot => write!(f, ":rot"),
+            Pick => write!(f, ":pick"),
+            Roll => write!(f, ":roll"),
+            While => write!(f, ":while"),
+            Until => write!(f, ":until"),
+            SetMaxIterations => write!(f, ":maxiter"),
+            Try => write!(f, ":try"),
+            Throw => write!(f, ":throw"),
             Store => write!(f, "s"),
             Load => write!(f, "l"),
             Apply => write!(f, "$"),
             Fun(fun) => write!(f, "{fun}"),
             Curried(fun, arg) => write!(f, "({arg} {fun})"),
             Identifier(ident) => write!(f, "{ident}"),
+            Global(ident) => write!(f, "::{ident}"),
+            Bind(params) => write!(f, "{{{} ->}}", params.join(" ")),
+            Lambda(params, body) if params.is_empty() => write!(f, "{{{body}}}"),
+            Lambda(params, body) => write!(f, "{{{} -> {body}}}", params.join(" ")),
             Composed(a, b) => write!(f, "({a} {b})"),
             LessThan => write!(f, "<"),
             GreaterThan => write!(f, ">"),
             Equal => write!(f, "="),
             Conditional => write!(f, "?"),
+            If => write!(f, ":if"),
+            SetScale => write!(f, "k"),
+            GetScale => write!(f, "K"),
+            SetRounding => write!(f, "R"),
+            SetInputRadix => write!(f, "i"),
+            GetInputRadix => write!(f, "I"),
+            SetOutputRadix => write!(f, "o"),
+            GetOutputRadix => write!(f, "O"),
+            SetNotation => write!(f, "N"),
             default => write!(f, "{default:?}"),
         }
     }
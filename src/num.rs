@@ -0,0 +1,818 @@
+use crate::Result;
+use bigdecimal::{
+    num_bigint::BigInt, BigDecimal, Context, FromPrimitive, RoundingMode, Signed, ToPrimitive, Zero,
+};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use std::{cmp::Ordering, fmt, num::NonZeroU64, ops, str::FromStr};
+
+/// Larger exponents in literals would take ages to expand, e.g. `1e999999999`.
+const MAX_EXPONENT: u64 = 10_000;
+
+// A number with this many bits already takes 2 MB.
+const MAX_POWER_BITS: u64 = 1 << 24;
+
+/// Which representation newly created numbers use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Arbitrary precision decimals, like `dc`.
+    #[default]
+    Decimal,
+    /// Double precision floats. Faster, but inexact.
+    Float,
+}
+
+/// How many fractional digits division results get and how they are rounded to fit.
+/// Printing uses the same settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    pub scale: i64,
+    pub rounding: RoundingMode,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            scale: 20,
+            rounding: RoundingMode::Down,
+        }
+    }
+}
+
+impl Precision {
+    /// The rounding modes in the order the `R` operator numbers them.
+    const ROUNDING_MODES: [RoundingMode; 5] = [
+        RoundingMode::Down,
+        RoundingMode::HalfUp,
+        RoundingMode::HalfEven,
+        RoundingMode::Floor,
+        RoundingMode::Ceiling,
+    ];
+
+    pub fn set_rounding(&mut self, i: usize) -> Result<()> {
+        self.rounding = *Self::ROUNDING_MODES
+            .get(i)
+            .ok_or_else(|| format!("Rounding mode {i} does not exist"))?;
+        Ok(())
+    }
+}
+
+/// How decimal numbers are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    #[default]
+    Plain,
+    /// One digit before the point, e.g. `6.02e23`
+    Scientific,
+    /// Exponents are multiples of 3, e.g. `602e21`
+    Engineering,
+}
+
+impl TryFrom<usize> for Notation {
+    type Error = crate::error::Error;
+
+    fn try_from(i: usize) -> Result<Self> {
+        match i {
+            0 => Ok(Notation::Plain),
+            1 => Ok(Notation::Scientific),
+            2 => Ok(Notation::Engineering),
+            _ => Err(format!("Notation {i} does not exist").into()),
+        }
+    }
+}
+
+/// Which unit trigonometric functions use for angles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Angle {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+impl Angle {
+    /// Multiply by this to convert an angle to radians.
+    pub fn to_radians(self) -> f64 {
+        match self {
+            Angle::Radians => 1.0,
+            Angle::Degrees => std::f64::consts::PI / 180.0,
+        }
+    }
+}
+
+/// Decimal constants are computed with this many digits more than the scale before they are rounded to it.
+const GUARD_DIGITS: i64 = 10;
+
+/// Number type of the machine
+#[derive(Debug, Clone)]
+pub enum Num {
+    Float(f64),
+    Decimal(BigDecimal),
+}
+
+impl Num {
+    /// Convert a literal from the parser. `0x`, `0o` and `0b` prefixes override the radix.
+    pub fn parse(s: &str, radix: u32, mode: Mode) -> Result<Self> {
+        let s = s.replace('_', "");
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s.as_str()),
+        };
+        let (radix, mantissa, exponent) = match s.get(..2) {
+            Some("0x") => (16, &s[2..], "0"),
+            Some("0o") => (8, &s[2..], "0"),
+            Some("0b") => (2, &s[2..], "0"),
+            _ => {
+                let (mantissa, exponent) = s.split_once('e').unwrap_or((s, "0"));
+                (radix, mantissa, exponent)
+            }
+        };
+        if let Some(d) = mantissa
+            .chars()
+            .find(|c| c.to_digit(36).is_some_and(|d| d >= radix))
+        {
+            return Err(format!("Digit {d} is too large for base {radix}").into());
+        }
+        let exponent = match exponent.parse::<i64>() {
+            Ok(e) if e.unsigned_abs() <= MAX_EXPONENT => e,
+            Ok(_) => {
+                return Err(format!(
+                    "Exponent {exponent} is too large, the limit is {MAX_EXPONENT} digits"
+                )
+                .into())
+            }
+            Err(_) => return Err(format!("Invalid exponent {exponent}").into()),
+        };
+        let n = match (mode, radix) {
+            (Mode::Float, 10) => Num::Float(s.parse().map_err(|_| format!("Invalid number {s}"))?),
+            (Mode::Decimal, 10) => Num::Decimal(
+                BigDecimal::from_str(s).map_err(|e| format!("Invalid number {s}: {e}"))?,
+            ),
+            _ => Num::from_radix(mantissa, exponent, radix, mode),
+        };
+        Ok(if negative { -n } else { n })
+    }
+
+    /// Digits need to be validated beforehand, and the exponent limited to `MAX_EXPONENT`.
+    /// The exponent counts digits, so it’s a power of the radix.
+    fn from_radix(digits: &str, exponent: i64, radix: u32, mode: Mode) -> Self {
+        let (int, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let mantissa =
+            BigInt::parse_bytes(format!("{int}{fraction}").as_bytes(), radix).unwrap_or_default();
+        // How far the point has to move to the right to make the mantissa an integer.
+        let shift = exponent - fraction.len() as i64;
+        match mode {
+            Mode::Float => Num::Float(
+                mantissa.to_f64().unwrap_or(f64::NAN)
+                    * (radix as f64).powi(i32::try_from(shift).expect("limited exponent")),
+            ),
+            Mode::Decimal if shift >= 0 => Num::Decimal(
+                (mantissa
+                    * BigInt::from(radix).pow(u32::try_from(shift).expect("limited exponent")))
+                .into(),
+            ),
+            Mode::Decimal => {
+                // Every base up to 36 whose fractions terminate in decimal (2, 4, 5, 8, 16, …)
+                // needs at most 5 decimal digits per digit, e.g. 1/32 = 0.03125.
+                // The others get at least the default scale.
+                let precision = Precision {
+                    scale: (5 * -shift).max(Precision::default().scale),
+                    rounding: RoundingMode::Down,
+                };
+                let denominator = BigInt::from(radix)
+                    .pow(u32::try_from(shift.unsigned_abs()).expect("limited exponent"));
+                Num::Decimal(div_decimal(mantissa.into(), denominator.into(), precision))
+            }
+        }
+    }
+
+    /// Exact for decimals. Floats keep all of their binary digits, so `0.1` is not `1/10`.
+    pub fn to_rational(&self) -> Result<BigRational> {
+        match self {
+            Num::Decimal(d) => {
+                let (n, scale) = d.as_bigint_and_scale();
+                let power = BigInt::from(10).pow(scale.unsigned_abs() as u32);
+                Ok(if scale >= 0 {
+                    BigRational::new(n.into_owned(), power)
+                } else {
+                    BigRational::from_integer(n.into_owned() * power)
+                })
+            }
+            Num::Float(f) => BigRational::from_float(*f)
+                .ok_or_else(|| format!("{f} is not a finite number").into()),
+        }
+    }
+
+    pub fn from_rational(r: &BigRational, mode: Mode, precision: Precision) -> Self {
+        match mode {
+            Mode::Float => Num::Float(r.to_f64().unwrap_or(f64::NAN)),
+            Mode::Decimal => Num::Decimal(div_decimal(
+                r.numer().clone().into(),
+                r.denom().clone().into(),
+                precision,
+            )),
+        }
+    }
+
+    /// Decimals get the shortest representation of the float, not all of its binary digits.
+    pub fn from_f64(f: f64, mode: Mode) -> Self {
+        match mode {
+            Mode::Decimal if f.is_finite() => {
+                BigDecimal::from_str(&f.to_string()).map_or(Num::Float(f), Num::Decimal)
+            }
+            _ => Num::Float(f),
+        }
+    }
+
+    pub fn int(n: i64, mode: Mode) -> Self {
+        match mode {
+            Mode::Float => Num::Float(n as f64),
+            Mode::Decimal => Num::Decimal(n.into()),
+        }
+    }
+
+    /// Only works for numbers without fractional digits, anything else would need rounding first.
+    pub fn to_bigint(&self) -> Result<BigInt> {
+        match self {
+            Num::Decimal(d) if d.is_integer() => Ok(d.with_scale(0).into_bigint_and_scale().0),
+            Num::Float(f) if f.fract() == 0.0 => Ok(BigInt::from_f64(*f).unwrap_or_default()),
+            n => Err(format!("{n} is not an integer").into()),
+        }
+    }
+
+    pub fn from_bigint(i: BigInt, mode: Mode) -> Self {
+        match mode {
+            Mode::Float => Num::Float(i.to_f64().unwrap_or(f64::NAN)),
+            Mode::Decimal => Num::Decimal(i.into()),
+        }
+    }
+
+    /// Round to an integer. Only `Floor`, `Ceiling`, `HalfUp` and `Down` are supported for floats.
+    pub fn round_to_integer(self, rounding: RoundingMode) -> Self {
+        match self {
+            Num::Decimal(d) => Num::Decimal(d.with_scale_round(0, rounding)),
+            Num::Float(f) => Num::Float(match rounding {
+                RoundingMode::Floor => f.floor(),
+                RoundingMode::Ceiling => f.ceil(),
+                RoundingMode::HalfUp => f.round(),
+                _ => f.trunc(),
+            }),
+        }
+    }
+
+    /// Machin’s formula, π = 16 atan(1/5) - 4 atan(1/239)
+    pub fn pi(mode: Mode, precision: Precision) -> Self {
+        Self::constant(std::f64::consts::PI, mode, precision, |one| {
+            16 * atan_inverse(5, one) - 4 * atan_inverse(239, one)
+        })
+    }
+
+    /// e = 1/0! + 1/1! + 1/2! + …
+    pub fn e(mode: Mode, precision: Precision) -> Self {
+        Self::constant(std::f64::consts::E, mode, precision, |one| {
+            let (mut sum, mut term) = (BigInt::zero(), one.clone());
+            for k in 1u32.. {
+                if term.is_zero() {
+                    break;
+                }
+                sum += &term;
+                term /= k;
+            }
+            sum
+        })
+    }
+
+    /// `decimal` computes the constant as a fixed point number, given what 1 is.
+    fn constant(
+        float: f64,
+        mode: Mode,
+        precision: Precision,
+        decimal: impl FnOnce(&BigInt) -> BigInt,
+    ) -> Self {
+        match mode {
+            Mode::Float => Num::Float(float),
+            Mode::Decimal => {
+                let digits = precision.scale.max(0) + GUARD_DIGITS;
+                let one = BigInt::from(10).pow(digits as u32);
+                Num::Decimal(BigDecimal::new(decimal(&one), digits)).round(precision)
+            }
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Num::Float(f) => *f,
+            Num::Decimal(d) => d.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        match self {
+            Num::Float(f) => Num::Float(f.abs()),
+            Num::Decimal(d) => Num::Decimal(d.abs()),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Num::Float(f) => *f == 0.0,
+            Num::Decimal(d) => d.is_zero(),
+        }
+    }
+
+    /// Floats ignore the scale when dividing, they can only be rounded for printing.
+    pub fn div(self, rhs: Num, precision: Precision) -> Result<Num> {
+        match (self, rhs) {
+            (Num::Decimal(_), Num::Decimal(b)) if b.is_zero() => Err("Division by zero".into()),
+            (Num::Decimal(a), Num::Decimal(b)) => Ok(Num::Decimal(div_decimal(a, b, precision))),
+            (a, b) => Ok(Num::Float(a.to_f64() / b.to_f64())),
+        }
+    }
+
+    pub fn rem(self, rhs: Num, precision: Precision) -> Result<Num> {
+        match (self, rhs) {
+            (Num::Decimal(_), Num::Decimal(b)) if b.is_zero() => Err("Division by zero".into()),
+            (Num::Decimal(a), Num::Decimal(b)) => Ok(Num::Decimal(a % b).round(precision)),
+            (a, b) => Ok(Num::Float(a.to_f64() % b.to_f64())),
+        }
+    }
+
+    /// Decimal roots get `scale` fractional digits like in `dc`.
+    /// `None` for negative numbers, whose roots are complex.
+    pub fn sqrt(self, precision: Precision) -> Option<Num> {
+        match self {
+            Num::Float(f) if f >= 0.0 => Some(Num::Float(f.sqrt())),
+            Num::Decimal(d) if !d.is_negative() => {
+                // Enough significant digits for the integer part and the scale, plus a few for rounding.
+                let digits = (d.order_of_magnitude() / 2 + precision.scale + 3).max(1);
+                let context = Context::default()
+                    .with_prec(digits)?
+                    .with_rounding_mode(RoundingMode::Down);
+                Some(Num::Decimal(d.sqrt_with_context(&context)?).round(precision))
+            }
+            _ => None,
+        }
+    }
+
+    /// Integer powers of decimals are exact, negative ones are rounded to the scale like a division.
+    /// Everything else goes through floats. `None` if the result would be complex.
+    pub fn pow(self, exponent: Num, precision: Precision) -> Result<Option<Num>> {
+        match (self, exponent) {
+            (Num::Decimal(a), Num::Decimal(b)) if b.is_integer() => {
+                let e = b
+                    .to_i64()
+                    .and_then(|b| u32::try_from(b.unsigned_abs()).ok())
+                    .ok_or_else(|| format!("Exponent {b} is too large"))?;
+                let (int, scale) = a.into_bigint_and_scale();
+                check_power(&int, e.into())?;
+                let power = BigDecimal::new(int.pow(e), scale * e as i64);
+                if !b.is_negative() {
+                    Ok(Some(Num::Decimal(power)))
+                } else if power.is_zero() {
+                    Err("Division by zero".into())
+                } else {
+                    Ok(Some(Num::Decimal(div_decimal(1.into(), power, precision))))
+                }
+            }
+            (a, b) => {
+                let mode = match (&a, &b) {
+                    (Num::Decimal(_), Num::Decimal(_)) => Mode::Decimal,
+                    _ => Mode::Float,
+                };
+                let (a, b) = (a.to_f64(), b.to_f64());
+                Ok((a >= 0.0 || b.fract() == 0.0).then(|| Num::from_f64(a.powf(b), mode)))
+            }
+        }
+    }
+
+    /// In scientific and engineering notation, the scale applies to the digits after the first one.
+    pub fn to_string_notation(&self, notation: Notation, precision: Precision) -> String {
+        let d = match self {
+            Num::Decimal(d) => d.clone(),
+            Num::Float(f) => match BigDecimal::from_str(&f.to_string()) {
+                Ok(d) => d,
+                Err(_) => return f.to_string(),
+            },
+        };
+        let digits = NonZeroU64::new(precision.scale as u64 + 1).unwrap_or(NonZeroU64::MIN);
+        let d = d
+            .with_precision_round(digits, precision.rounding)
+            .normalized();
+        match notation {
+            Notation::Plain => d.to_plain_string(),
+            Notation::Scientific => d.to_scientific_notation(),
+            Notation::Engineering => d.to_engineering_notation(),
+        }
+    }
+
+    /// Digits above 9 are uppercase letters. Only the fractional digits needed
+    /// to match the precision of the decimal value are printed.
+    pub fn to_string_radix(&self, radix: u32) -> String {
+        let d = match self {
+            _ if radix == 10 => return self.to_string(),
+            Num::Decimal(d) => d.normalized(),
+            Num::Float(f) => match BigDecimal::from_str(&f.to_string()) {
+                Ok(d) => d,
+                Err(_) => return f.to_string(),
+            },
+        };
+        let sign = if d.is_negative() { "-" } else { "" };
+        let d = d.abs();
+        let int = d.with_scale_round(0, RoundingMode::Down);
+        let mut fraction = &d - &int;
+        let mut out = format!(
+            "{sign}{}",
+            int.into_bigint_and_scale()
+                .0
+                .to_str_radix(radix)
+                .to_uppercase()
+        );
+        if !fraction.is_zero() {
+            out.push('.');
+            let max_digits = (d.fractional_digit_count() as f64 * 10f64.ln() / (radix as f64).ln())
+                .ceil() as usize;
+            for _ in 0..max_digits {
+                fraction *= BigDecimal::from(radix);
+                let digit = fraction.with_scale_round(0, RoundingMode::Down);
+                fraction -= &digit;
+                let digit = digit.to_u32().unwrap_or_default();
+                out.push(
+                    char::from_digit(digit, radix)
+                        .unwrap_or('?')
+                        .to_ascii_uppercase(),
+                );
+                if fraction.is_zero() {
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    /// Cut off all fractional digits beyond the scale. Never adds trailing zeros.
+    pub fn round(self, precision: Precision) -> Num {
+        let Precision { scale, rounding } = precision;
+        match self {
+            Num::Decimal(d) if d.fractional_digit_count() > scale => {
+                Num::Decimal(d.with_scale_round(scale, rounding))
+            }
+            // Going through the shortest representation so we don’t round the binary noise
+            // beyond it, e.g. 0.1 would otherwise be 0.1000000000000000055511151231257827.
+            Num::Float(f) if f.is_finite() => match BigDecimal::from_str(&f.to_string()) {
+                Ok(d) if d.fractional_digit_count() > scale => d
+                    .with_scale_round(scale, rounding)
+                    .to_f64()
+                    .map_or(self, Num::Float),
+                _ => self,
+            },
+            n => n,
+        }
+    }
+}
+
+/// atan(1/x) = 1/x - 1/3x³ + 1/5x⁵ - … as a fixed point number, given what 1 is.
+fn atan_inverse(x: u32, one: &BigInt) -> BigInt {
+    let x_squared = BigInt::from(x * x);
+    let mut power = one / x;
+    let (mut sum, mut k) = (BigInt::zero(), 1u32);
+    while !power.is_zero() {
+        let term = &power / k;
+        if k % 4 == 1 {
+            sum += term;
+        } else {
+            sum -= term;
+        }
+        power /= &x_squared;
+        k += 2;
+    }
+    sum
+}
+
+/// Integer powers are limited to `MAX_POWER_BITS` bits so they can’t use up all memory.
+pub fn check_power(base: &BigInt, exponent: u64) -> Result<()> {
+    // 0 and ±1 stay small, every other base adds at least one bit per multiplication.
+    match base.bits().saturating_sub(1).saturating_mul(exponent) {
+        bits if bits <= MAX_POWER_BITS => Ok(()),
+        _ => Err(format!(
+            "Exponent {exponent} is too large, the result would have more than {MAX_POWER_BITS} bits"
+        )
+        .into()),
+    }
+}
+
+/// For dividing rationals, which would panic otherwise.
+pub fn nonzero(r: BigRational) -> Result<BigRational> {
+    if r.is_zero() {
+        Err("Division by zero".into())
+    } else {
+        Ok(r)
+    }
+}
+
+/// Convert the `1/3` of a `1/3r` literal.
+pub fn parse_rational(s: &str, radix: u32) -> Result<BigRational> {
+    let parse = |s: &str| {
+        BigInt::parse_bytes(s.replace('_', "").as_bytes(), radix)
+            .ok_or_else(|| format!("Invalid digits {s} for base {radix}"))
+    };
+    let (numerator, denominator) = s
+        .split_once('/')
+        .ok_or_else(|| format!("Invalid fraction {s}"))?;
+    let denominator = parse(denominator)?;
+    if denominator.is_zero() {
+        return Err("Division by zero".into());
+    }
+    Ok(BigRational::new(parse(numerator)?, denominator))
+}
+
+/// Convert the `3+4` of a `3+4j` literal. Both parts are floats.
+pub fn parse_complex(s: &str, radix: u32) -> Result<Complex64> {
+    let float = |s: &str| Num::parse(s, radix, Mode::Float).map(|n| n.to_f64());
+    // The sign between the parts could also be part of an exponent, so we try every candidate.
+    for (i, c) in s.char_indices().rev().filter(|&(i, _)| i > 0) {
+        if let ('+' | '-', Ok(re), Ok(im)) = (c, float(&s[..i]), float(&s[i + 1..])) {
+            return Ok(Complex64::new(re, if c == '-' { -im } else { im }));
+        }
+    }
+    Ok(Complex64::new(0.0, float(s)?))
+}
+
+/// Division with exactly `scale` fractional digits.
+/// `BigDecimal` itself divides to a fixed number of significant digits instead.
+fn div_decimal(a: BigDecimal, b: BigDecimal, precision: Precision) -> BigDecimal {
+    let Precision { scale, rounding } = precision;
+    let (a, a_scale) = a.into_bigint_and_scale();
+    let (b, b_scale) = b.into_bigint_and_scale();
+    // One digit more than we need so the rounding mode can look at it.
+    let exponent = b_scale - a_scale + scale + 1;
+    let (n, d) = if exponent >= 0 {
+        (a * BigInt::from(10).pow(exponent as u32), b)
+    } else {
+        (a, b * BigInt::from(10).pow(exponent.unsigned_abs() as u32))
+    };
+    let negative = n.is_negative() != d.is_negative();
+    let remainder = &n % &d;
+    let mut quotient = n / d;
+    let mut quotient_scale = scale + 1;
+    if !remainder.is_zero() {
+        // Whatever got cut off is smaller than one more digit.
+        // Appending a 1 tells the rounding that the result wasn’t exact, e.g. not an exact tie.
+        quotient = quotient * 10 + if negative { -1 } else { 1 };
+        quotient_scale += 1;
+    }
+    BigDecimal::new(quotient, quotient_scale).with_scale_round(scale, rounding)
+}
+
+// Mixing both representations only happens when values from different modes meet,
+// in which case the inexact one wins.
+macro_rules! arithmetic {
+    ($trait:ident, $f:ident) => {
+        impl ops::$trait for Num {
+            type Output = Num;
+
+            fn $f(self, rhs: Num) -> Num {
+                match (self, rhs) {
+                    (Num::Decimal(a), Num::Decimal(b)) => Num::Decimal(ops::$trait::$f(a, b)),
+                    (a, b) => Num::Float(ops::$trait::$f(a.to_f64(), b.to_f64())),
+                }
+            }
+        }
+    };
+}
+
+impl ops::Neg for Num {
+    type Output = Num;
+
+    fn neg(self) -> Num {
+        match self {
+            Num::Float(f) => Num::Float(-f),
+            Num::Decimal(d) => Num::Decimal(-d),
+        }
+    }
+}
+
+arithmetic!(Add, add);
+arithmetic!(Sub, sub);
+arithmetic!(Mul, mul);
+
+impl From<f64> for Num {
+    fn from(f: f64) -> Self {
+        Num::Float(f)
+    }
+}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Num::Decimal(a), Num::Decimal(b)) => a.partial_cmp(b),
+            (a, b) => a.to_f64().partial_cmp(&b.to_f64()),
+        }
+    }
+}
+
+// Numbers are compared by value, regardless of their representation.
+impl PartialEq for Num {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Num::Float(n) => write!(f, "{n}"),
+            Num::Decimal(d) => write!(f, "{}", d.normalized().to_plain_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("1" => 1.0)]
+    #[test_case("1." => 1.0; "trailing dot")]
+    #[test_case(".5" => 0.5; "leading dot")]
+    #[test_case("-.5" => -0.5; "negative leading dot")]
+    #[test_case("-2.25" => -2.25)]
+    fn parse_decimal(s: &str) -> f64 {
+        Num::parse(s, 10, Mode::Decimal)
+            .expect("invalid number")
+            .to_f64()
+    }
+
+    #[test_case("FF", 16 => 255.0)]
+    #[test_case("-0xff", 10 => -255.0; "hex prefix")]
+    #[test_case("0o17", 10 => 15.0; "octal prefix")]
+    #[test_case("0b101.1", 10 => 5.5; "binary prefix")]
+    #[test_case("0x.8", 2 => 0.5; "prefix overrides radix")]
+    #[test_case("0.1", 3 => 0.3333333333333333; "non-terminating fraction")]
+    #[test_case("11", 2 => 3.0)]
+    fn parse_radix(s: &str, radix: u32) -> f64 {
+        let decimal = Num::parse(s, radix, Mode::Decimal).expect("invalid number");
+        let float = Num::parse(s, radix, Mode::Float).expect("invalid number");
+        assert!((decimal.to_f64() - float.to_f64()).abs() < 1e-15);
+        decimal.to_f64()
+    }
+
+    #[test_case("12", 2)]
+    #[test_case("1A", 10)]
+    #[test_case("0b12", 16; "prefix")]
+    fn reject_digits_outside_radix(s: &str, radix: u32) {
+        assert!(Num::parse(s, radix, Mode::Decimal).is_err());
+    }
+
+    #[test_case("255", 16 => "FF")]
+    #[test_case("-255", 2 => "-11111111")]
+    #[test_case("0.5", 2 => "0.1")]
+    #[test_case("10.75", 16 => "A.C")]
+    #[test_case("0.1", 2 => "0.0001"; "cut off after matching the precision")]
+    #[test_case("35", 36 => "Z")]
+    #[test_case("1.5", 10 => "1.5")]
+    fn output_radix(s: &str, radix: u32) -> String {
+        let decimal = Num::parse(s, 10, Mode::Decimal)
+            .unwrap()
+            .to_string_radix(radix);
+        assert_eq!(
+            decimal,
+            Num::parse(s, 10, Mode::Float)
+                .unwrap()
+                .to_string_radix(radix)
+        );
+        decimal
+    }
+
+    #[test_case("1e9" => 1e9)]
+    #[test_case("6.02e23" => 6.02e23)]
+    #[test_case("-1.5e-3" => -1.5e-3)]
+    #[test_case("1_000_000" => 1e6; "separators")]
+    #[test_case("0xFF_FF" => 65535.0; "separators with prefix")]
+    fn parse_scientific(s: &str) -> f64 {
+        let decimal = Num::parse(s, 10, Mode::Decimal).expect("invalid number");
+        assert_eq!(
+            decimal,
+            Num::parse(s, 10, Mode::Float).expect("invalid number")
+        );
+        decimal.to_f64()
+    }
+
+    #[test_case("1e3", 16 => 4096.0; "exponent is a power of the radix")]
+    #[test_case("1.1e-1", 2 => 0.75)]
+    fn parse_exponent_radix(s: &str, radix: u32) -> f64 {
+        Num::parse(s, radix, Mode::Decimal)
+            .expect("invalid number")
+            .to_f64()
+    }
+
+    #[test_case("1e999999999", 10)]
+    #[test_case("1e-10001", 10; "negative")]
+    #[test_case("1e100000001", 16; "radix")]
+    #[test_case("1e4294967297", 16; "would be truncated to 32 bits")]
+    #[test_case("1e99999999999999999999", 10; "not an i64")]
+    fn reject_huge_exponents(s: &str, radix: u32) {
+        assert!(Num::parse(s, radix, Mode::Decimal).is_err());
+        assert!(Num::parse(s, radix, Mode::Float).is_err());
+    }
+
+    #[test_case("602200000000000000000000", Notation::Scientific => "6.02e23")]
+    #[test_case("602200000000000000000000", Notation::Engineering => "602e21")]
+    #[test_case("0.00012345", Notation::Scientific => "1.23e-4")]
+    #[test_case("0.0012", Notation::Engineering => "1.2e-3")]
+    #[test_case("1.23456", Notation::Scientific => "1.23e0")]
+    fn notation(s: &str, notation: Notation) -> String {
+        let precision = Precision {
+            scale: 2,
+            rounding: RoundingMode::Down,
+        };
+        let decimal = Num::parse(s, 10, Mode::Decimal).unwrap();
+        let float = Num::parse(s, 10, Mode::Float).unwrap();
+        assert_eq!(
+            decimal.to_string_notation(notation, precision),
+            float.to_string_notation(notation, precision)
+        );
+        decimal.to_string_notation(notation, precision)
+    }
+
+    #[test_case("1/3", 10 => "1/3")]
+    #[test_case("-2/4", 10 => "-1/2"; "reduced")]
+    #[test_case("A/F", 16 => "2/3")]
+    #[test_case("6/3", 10 => "2")]
+    fn rationals(s: &str, radix: u32) -> String {
+        parse_rational(s, radix).unwrap().to_string()
+    }
+
+    #[test_case("3+4" => Complex64::new(3.0, 4.0))]
+    #[test_case("-2.5-1" => Complex64::new(-2.5, -1.0))]
+    #[test_case("-2" => Complex64::new(0.0, -2.0); "imaginary")]
+    #[test_case("1e-3-2e+3" => Complex64::new(1e-3, -2e3); "exponents")]
+    #[test_case("1e+2" => Complex64::new(0.0, 100.0); "imaginary with exponent")]
+    #[test_case("0x1e+0b1" => Complex64::new(30.0, 1.0); "prefixes")]
+    fn complex(s: &str) -> Complex64 {
+        parse_complex(s, 10).unwrap()
+    }
+
+    #[test_case("0.25" => "1/4")]
+    #[test_case("-1.5" => "-3/2")]
+    #[test_case("2e3" => "2000")]
+    fn decimal_to_rational(s: &str) -> String {
+        Num::parse(s, 10, Mode::Decimal)
+            .unwrap()
+            .to_rational()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test_case(Mode::Decimal => "0.3")]
+    #[test_case(Mode::Float => "0.30000000000000004")]
+    fn exactness(mode: Mode) -> String {
+        let n = |s| Num::parse(s, 10, mode).unwrap();
+        (n("0.1") + n("0.2")).to_string()
+    }
+
+    #[test]
+    fn big_integers() {
+        let n = Num::parse("123456789012345678901234567890", 10, Mode::Decimal).unwrap();
+        assert_eq!(
+            (n.clone() * n).to_string(),
+            "15241578753238836750495351562536198787501905199875019052100"
+        );
+    }
+
+    #[test]
+    fn decimal_division_by_zero() {
+        let n = |s| Num::parse(s, 10, Mode::Decimal).unwrap();
+        assert!(n("1").div(n("0"), Precision::default()).is_err());
+        assert!(n("1").rem(n("0"), Precision::default()).is_err());
+    }
+
+    #[test_case("2", "3", 5, RoundingMode::Down => "0.66666")]
+    #[test_case("2", "3", 5, RoundingMode::HalfUp => "0.66667")]
+    #[test_case("-2", "3", 2, RoundingMode::Floor => "-0.67")]
+    #[test_case("-2", "3", 2, RoundingMode::Down => "-0.66")]
+    #[test_case("1", "8", 2, RoundingMode::HalfEven => "0.12"; "exact tie")]
+    #[test_case("1000001", "8000000", 2, RoundingMode::HalfEven => "0.13"; "almost a tie")]
+    #[test_case("7", "2", 0, RoundingMode::Down => "3")]
+    #[test_case("0.0001", "0.2", 2, RoundingMode::Down => "0")]
+    #[test_case("123.45", "0.005", 1, RoundingMode::Down => "24690")]
+    fn scaled_division(a: &str, b: &str, scale: i64, rounding: RoundingMode) -> String {
+        let n = |s| Num::parse(s, 10, Mode::Decimal).unwrap();
+        n(a).div(n(b), Precision { scale, rounding })
+            .unwrap()
+            .to_string()
+    }
+
+    #[test_case(Mode::Decimal => "0.33")]
+    #[test_case(Mode::Float => "0.33")]
+    fn rounding(mode: Mode) -> String {
+        let precision = Precision {
+            scale: 2,
+            rounding: RoundingMode::HalfEven,
+        };
+        let n = |s| Num::parse(s, 10, mode).unwrap();
+        n("1")
+            .div(n("3"), precision)
+            .unwrap()
+            .round(precision)
+            .to_string()
+    }
+}
@@ -1,26 +1,43 @@
 use crate::V;
 use nom::{
     branch::alt,
-    character::complete::{alphanumeric1, char, digit0, digit1, multispace0, one_of},
-    combinator::{map, opt, recognize, rest, value, verify},
-    multi::many0,
-    sequence::{delimited, preceded},
+    bytes::complete::{is_not, tag, take_while},
+    character::complete::{alphanumeric1, char, digit1, multispace0, one_of, satisfy},
+    combinator::{consumed, map, map_opt, opt, recognize, rest, value, verify},
+    error::Error,
+    multi::{many0, many1},
+    sequence::{delimited, preceded, terminated},
     IResult, Parser,
 };
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 pub fn parse(input: &str) -> IResult<&str, Vec<V>> {
+    let (rest, values) = parse_with_offsets(input)?;
+    Ok((rest, values.into_iter().map(|(_, v)| v).collect()))
+}
+
+/// Like `parse`, but with the byte offset of the token that each value comes from.
+pub fn parse_with_offsets(input: &str) -> IResult<&str, Vec<(usize, V)>> {
     let function_mode = AtomicBool::new(false);
     let functions = AtomicUsize::new(0);
+    // The names of `{a b -> …}`, if the current function has parameters
+    let params = RefCell::new(None::<Vec<String>>);
+    let is_param = |v: &V| match (v, &*params.borrow()) {
+        (V::Identifier(id), Some(params)) => params.contains(id),
+        _ => false,
+    };
     many0(preceded(
         multispace0,
-        alt((
-            map(float, |f| {
+        consumed(alt((
+            map(literal, |n| {
                 // While in function mode, automatically curry values.
                 if function_mode.load(Ordering::Relaxed) {
-                    vec![V::Value(f), V::Curry]
+                    vec![n, V::Curry]
                 } else {
-                    vec![V::Value(f)]
+                    vec![n]
                 }
             }),
             map(
@@ -31,16 +48,24 @@ pub fn parse(input: &str) -> IResult<&str, Vec<V>> {
                         // or for Compose and Curry which are always eager.
                         cannot_be_lazy(op) || !function_mode.load(Ordering::Relaxed)
                     }),
-                    identifier,
+                    verify(identifier, |id| !is_param(id)),
                 )),
                 |o| vec![o],
             ),
             map(
                 alt((
                     map(partial_op_inner, |o| vec![o]),
+                    // Parameters are functions that push their value
+                    map(verify(identifier, is_param), |o| vec![o]),
                     map(char('}'), |_| {
                         function_mode.store(false, Ordering::Relaxed);
-                        Vec::new()
+                        // Every function gets a scope, also without parameters.
+                        let params = params.take().unwrap_or_default();
+                        match functions.load(Ordering::Relaxed) {
+                            // A function without a body still needs something to bind the parameters to
+                            0 => vec![V::Quotation(Vec::new()), V::Bind(params)],
+                            _ => vec![V::Bind(params)],
+                        }
                     }),
                 )),
                 |mut v| {
@@ -50,15 +75,24 @@ pub fn parse(input: &str) -> IResult<&str, Vec<V>> {
                     v
                 },
             ),
-            map(char('{'), |_| {
+            map(preceded(char('{'), opt(parameters)), |names| {
                 function_mode.store(true, Ordering::Relaxed);
                 functions.store(0, Ordering::Relaxed);
+                params.replace(names);
                 Vec::new()
             }),
             value(Vec::new(), comment),
-        )),
+        ))),
     ))
-    .map(|v| v.into_iter().flatten().collect())
+    .map(|tokens| {
+        tokens
+            .into_iter()
+            .flat_map(|(token, values)| {
+                let offset = token.as_ptr() as usize - input.as_ptr() as usize;
+                values.into_iter().map(move |v| (offset, v))
+            })
+            .collect()
+    })
     .parse(input)
 }
 
@@ -66,10 +100,24 @@ fn cannot_be_lazy(op: &V) -> bool {
     matches!(op, &V::Curry | &V::Compose)
 }
 
+/// `(name)`, or `(::name)` for the global variable
 fn identifier(input: &str) -> IResult<&str, V> {
-    map(delimited(char('('), alphanumeric1, char(')')), |s: &str| {
-        V::Identifier(s.to_owned())
-    })
+    map(
+        delimited(char('('), (opt(tag("::")), alphanumeric1), char(')')),
+        |(global, s): (_, &str)| match global {
+            Some(_) => V::Global(s.to_owned()),
+            None => V::Identifier(s.to_owned()),
+        },
+    )
+    .parse(input)
+}
+
+/// The `a b ->` of `{a b -> …}`
+fn parameters(input: &str) -> IResult<&str, Vec<String>> {
+    terminated(
+        many1(preceded(multispace0, map(alphanumeric1, str::to_owned))),
+        preceded(multispace0, tag("->")),
+    )
     .parse(input)
 }
 
@@ -77,13 +125,13 @@ fn comment(input: &str) -> IResult<&str, &str> {
     preceded(char('#'), rest).parse(input)
 }
 
-const OP0: &str = "fcqS";
-const OP1: &str = "p$";
-const OP2: &str = "+-*/%slr<>=|@";
+const OP0: &str = "fcqSKIO";
+const OP1: &str = "p$kRioNvdxP";
+const OP2: &str = "+-*/%slr<>=|@^";
 const OP3: &str = "?";
 
 fn op(input: &str) -> IResult<&str, V> {
-    alt((op0, op1, op2, op3)).parse(input)
+    alt((op0, op1, op2, op3, named_op)).parse(input)
 }
 
 fn partial_op(input: &str) -> IResult<&str, V> {
@@ -100,6 +148,9 @@ fn op0(input: &str) -> IResult<&str, V> {
         'c' => V::Clear,
         'q' => V::Quit,
         'S' => V::Stacksize,
+        'K' => V::GetScale,
+        'I' => V::GetInputRadix,
+        'O' => V::GetOutputRadix,
         _ => unreachable!(),
     })
     .parse(input)
@@ -109,6 +160,15 @@ fn op1(input: &str) -> IResult<&str, V> {
     map(one_of(OP1), |c| match c {
         'p' => V::Print,
         '$' => V::Apply,
+        'k' => V::SetScale,
+        'R' => V::SetRounding,
+        'i' => V::SetInputRadix,
+        'o' => V::SetOutputRadix,
+        'N' => V::SetNotation,
+        'v' => V::Sqrt,
+        'd' => V::Dup,
+        'x' => V::Execute,
+        'P' => V::PrintRaw,
         _ => unreachable!(),
     })
     .parse(input)
@@ -129,6 +189,7 @@ fn op2(input: &str) -> IResult<&str, V> {
         '=' => V::Equal,
         '|' => V::Compose,
         '@' => V::Curry,
+        '^' => V::Pow,
         _ => unreachable!(),
     })
     .parse(input)
@@ -142,30 +203,180 @@ fn op3(input: &str) -> IResult<&str, V> {
     .parse(input)
 }
 
-fn float(input: &str) -> IResult<&str, f64> {
+/// Operators that are written as `:name` because we ran out of characters.
+fn named_op(input: &str) -> IResult<&str, V> {
+    map_opt(preceded(char(':'), alphanumeric1), |name| {
+        Some(match name {
+            "dec" => V::ToDecimal,
+            "rat" => V::ToRational,
+            "abs" => V::Abs,
+            "arg" => V::Arg,
+            "conj" => V::Conj,
+            "re" => V::Re,
+            "im" => V::Im,
+            "ln" => V::Ln,
+            "log" => V::Log,
+            "exp" => V::Exp,
+            "sin" => V::Sin,
+            "cos" => V::Cos,
+            "tan" => V::Tan,
+            "asin" => V::Asin,
+            "acos" => V::Acos,
+            "atan" => V::Atan,
+            "pi" => V::Pi,
+            "e" => V::Euler,
+            "deg" => V::Degrees,
+            "rad" => V::Radians,
+            "and" => V::And,
+            "or" => V::Or,
+            "xor" => V::Xor,
+            "not" => V::Not,
+            "shl" => V::ShiftLeft,
+            "shr" => V::ShiftRight,
+            "popcnt" => V::Popcount,
+            "div" => V::IntDiv,
+            "floor" => V::Floor,
+            "ceil" => V::Ceil,
+            "trunc" => V::Trunc,
+            "round" => V::Round,
+            "cat" => V::Concat,
+            "len" => V::Length,
+            "substr" => V::Substring,
+            "quote" => V::Quote,
+            "pack" => V::Pack,
+            "unpack" => V::Unpack,
+            "get" => V::Get,
+            "append" => V::Append,
+            "slice" => V::Slice,
+            "map" => V::Map,
+            "filter" => V::Filter,
+            "fold" => V::Fold,
+            "scan" => V::Scan,
+            "sortby" => V::SortBy,
+            "zip" => V::Zip,
+            "sort" => V::Sort,
+            "range" => V::Range,
+            "rangeby" => V::RangeBy,
+            "linspace" => V::Linspace,
+            "if" => V::If,
+            "while" => V::While,
+            "until" => V::Until,
+            "maxiter" => V::SetMaxIterations,
+            "try" => V::Try,
+            "throw" => V::Throw,
+            "swap" => V::Swap,
+            "drop" => V::Drop,
+            "over" => V::Over,
+            "rot" => V::Rot,
+            "pick" => V::Pick,
+            "roll" => V::Roll,
+            _ => return None,
+        })
+    })
+    .parse(input)
+}
+
+fn literal(input: &str) -> IResult<&str, V> {
+    alt((
+        string,
+        list,
+        map(alt((complex, rational, number)), |n: &str| {
+            V::Literal(n.to_owned())
+        }),
+    ))
+    .parse(input)
+}
+
+/// Lists of literals like `'(1 2 [three])`. Plain parentheses are already taken by identifiers.
+fn list(input: &str) -> IResult<&str, V> {
     map(
+        delimited(
+            tag("'("),
+            many0(preceded(multispace0, literal)),
+            preceded(multispace0, char(')')),
+        ),
+        V::List,
+    )
+    .parse(input)
+}
+
+/// Strings are in brackets like in `dc`. Brackets inside of them have to be balanced.
+pub(crate) fn string(input: &str) -> IResult<&str, V> {
+    map(string_brackets, |s| V::Str(s[1..s.len() - 1].to_owned())).parse(input)
+}
+
+fn string_brackets(input: &str) -> IResult<&str, &str> {
+    recognize(delimited(
+        char('['),
+        many0(alt((is_not("[]"), string_brackets))),
+        char(']'),
+    ))
+    .parse(input)
+}
+
+/// Numbers are only recognized here. Converting them is up to the machine,
+/// which knows the input radix and whether they should become decimals or floats.
+fn number(input: &str) -> IResult<&str, &str> {
+    recognize((opt(char('-')), unsigned_number)).parse(input)
+}
+
+fn unsigned_number(input: &str) -> IResult<&str, &str> {
+    alt((
+        preceded(tag("0x"), digits(|c| c.is_ascii_hexdigit())),
+        preceded(tag("0o"), digits(|c| c.is_digit(8))),
+        preceded(tag("0b"), digits(|c| c.is_digit(2))),
         recognize((
-            opt(char('-')),
-            // Parsers are greedy, so we need both cases
-            alt((
-                (digit0, opt(char('.')), digit1),
-                (digit1, opt(char('.')), digit0),
-            )),
+            digits(is_digit),
+            opt((char('e'), opt(one_of("+-")), digit1)),
         )),
-        |s: &str| {
-            s.parse()
-                .unwrap_or_else(|_| panic!("Failed to parse {s} as float"))
-        },
-    )
+    ))
     .parse(input)
 }
 
+/// Complex numbers like `3+4j` or `-2j`. It’s `j` because `i` sets the input radix.
+fn complex(input: &str) -> IResult<&str, &str> {
+    recognize((number, opt((one_of("+-"), unsigned_number)), char('j'))).parse(input)
+}
+
+/// Fractions like `1/3r`, which become exact rationals.
+fn rational(input: &str) -> IResult<&str, &str> {
+    let integer = || recognize((satisfy(is_digit), take_while(|c| is_digit(c) || c == '_')));
+    recognize((opt(char('-')), integer(), char('/'), integer(), char('r'))).parse(input)
+}
+
+/// Like in `dc`, digits above 9 are uppercase. Uppercase operators like `S` or `R` stay operators,
+/// so they can follow a number directly, e.g. `1R`.
+fn is_digit(c: char) -> bool {
+    c.is_ascii_digit() || c.is_ascii_uppercase() && ![OP0, OP1].iter().any(|ops| ops.contains(c))
+}
+
+/// Digits with an optional fractional part.
+/// Underscores can be used as separators, but not at the start.
+fn digits<'a>(
+    is_digit: fn(char) -> bool,
+) -> impl Parser<&'a str, Output = &'a str, Error = Error<&'a str>> {
+    let run = move || {
+        recognize((
+            satisfy(is_digit),
+            take_while(move |c| is_digit(c) || c == '_'),
+        ))
+    };
+    alt((
+        recognize((run(), opt((char('.'), opt(run()))))),
+        recognize((char('.'), run())),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::V::*;
+    use crate::{Mode, Num, V::*};
     use test_case::test_case;
 
+    fn lit(s: &str) -> V {
+        Literal(s.to_owned())
+    }
+
     fn assert_parses_as(s: &str, expected: &[V]) {
         let (rest, parsed) = parse(s).expect("parsing failed");
         assert_eq!("", rest);
@@ -174,10 +385,10 @@ mod tests {
 
     #[test]
     fn parse_comment() {
-        assert_parses_as("1 2+#gibberish", &[Value(1.0), Value(2.0), Add]);
+        assert_parses_as("1 2+#gibberish", &[lit("1"), lit("2"), Add]);
         assert_parses_as(
             "1 2+      #--#+234     more  gibberish",
-            &[Value(1.0), Value(2.0), Add],
+            &[lit("1"), lit("2"), Add],
         );
     }
 
@@ -187,28 +398,42 @@ mod tests {
     #[test_case("01.00" => 1.0)]
     #[test_case(".5" => 0.5; "leading dot")]
     #[test_case("0.5" => 0.5)]
-    fn float_parser(s: &str) -> f64 {
-        match float(s) {
-            Ok(("", f)) => f,
+    #[test_case("0x1F" => 31.0)]
+    #[test_case("0b101" => 5.0)]
+    #[test_case("1e9" => 1e9)]
+    #[test_case("6.02e+23" => 6.02e23)]
+    #[test_case("1.5e-3" => 1.5e-3)]
+    #[test_case("1_000.000_1" => 1000.0001)]
+    fn number_parser(s: &str) -> f64 {
+        match number(s) {
+            Ok(("", n)) => {
+                let decimal = Num::parse(n, 10, Mode::Decimal).expect("not a decimal");
+                assert_eq!(
+                    decimal,
+                    Num::parse(n, 10, Mode::Float).expect("not a float")
+                );
+                decimal.to_f64()
+            }
             e => panic!("{e:?}"),
         }
     }
 
     #[test_case("asdf")]
     #[test_case("a1")]
-    fn reject_invalid_floats(s: &str) {
-        assert!(matches!(float(s), Err(_)));
+    #[test_case("_1"; "leading separator")]
+    fn reject_invalid_numbers(s: &str) {
+        assert!(number(s).is_err());
     }
 
     #[test]
     fn parse_expression() {
-        assert_parses_as("1 2+3-", &[Value(1.0), Value(2.0), Add, Value(3.0), Sub]);
-        assert_parses_as(
-            "1 1-2--3",
-            &[Value(1.0), Value(1.0), Value(-2.0), Sub, Value(-3.0)],
-        );
-        assert_parses_as(".5.5", &[Value(0.5), Value(0.5)]);
-        assert_parses_as("4 4 +4", &[Value(4.0), Value(4.0), Add, Value(4.0)]);
+        assert_parses_as("1 2+3-", &[lit("1"), lit("2"), Add, lit("3"), Sub]);
+        assert_parses_as("1 1-2--3", &[lit("1"), lit("1"), lit("-2"), Sub, lit("-3")]);
+        assert_parses_as(".5.5", &[lit(".5"), lit(".5")]);
+        assert_parses_as("4 4 +4", &[lit("4"), lit("4"), Add, lit("4")]);
+        assert_parses_as("FF 1A 0xffp", &[lit("FF"), lit("1A"), lit("0xff"), Print]);
+        assert_parses_as("0b12", &[lit("0b1"), lit("2")]);
+        assert_parses_as("1e3p 1e-3", &[lit("1e3"), Print, lit("1e-3")]);
         let operators = format!("{OP0}{OP1}{OP2}{OP3}");
         assert_parses_as(
             &operators,
@@ -217,8 +442,20 @@ mod tests {
                 Clear,
                 Quit,
                 Stacksize,
+                GetScale,
+                GetInputRadix,
+                GetOutputRadix,
                 Print,
                 Apply,
+                SetScale,
+                SetRounding,
+                SetInputRadix,
+                SetOutputRadix,
+                SetNotation,
+                Sqrt,
+                Dup,
+                Execute,
+                PrintRaw,
                 Add,
                 Sub,
                 Mul,
@@ -232,11 +469,106 @@ mod tests {
                 Equal,
                 Compose,
                 Curry,
+                Pow,
                 Conditional,
             ],
         );
     }
 
+    #[test]
+    fn parse_named_operators() {
+        assert_parses_as(
+            ":dec:rat :abs:arg:conj:re:im",
+            &[ToDecimal, ToRational, Abs, Arg, Conj, Re, Im],
+        );
+        assert_parses_as(
+            ":ln:log:exp :sin:cos:tan :asin:acos:atan :pi:e :deg:rad",
+            &[
+                Ln, Log, Exp, Sin, Cos, Tan, Asin, Acos, Atan, Pi, Euler, Degrees, Radians,
+            ],
+        );
+        assert_parses_as(
+            ":and:or:xor:not :shl:shr:popcnt :div:floor:ceil:trunc:round",
+            &[
+                And, Or, Xor, Not, ShiftLeft, ShiftRight, Popcount, IntDiv, Floor, Ceil, Trunc,
+                Round,
+            ],
+        );
+        assert_parses_as(
+            ":cat:len:substr:quote :swap:drop:over:rot :pick:roll",
+            &[
+                Concat, Length, Substring, Quote, Swap, Drop, Over, Rot, Pick, Roll,
+            ],
+        );
+        assert_parses_as(
+            ":pack:unpack :get:append:slice",
+            &[Pack, Unpack, Get, Append, Slice],
+        );
+        assert_parses_as(
+            ":map:filter:fold:scan:sortby :zip:sort:range:rangeby:linspace",
+            &[
+                Map, Filter, Fold, Scan, SortBy, Zip, Sort, Range, RangeBy, Linspace,
+            ],
+        );
+        assert_parses_as(
+            ":if:while:until :maxiter:try:throw",
+            &[If, While, Until, SetMaxIterations, Try, Throw],
+        );
+        assert_parses_as(r"\:dec", &[Fun(Box::new(ToDecimal))]);
+        assert_parses_as(r"{:sin}", &[Fun(Box::new(Sin)), Bind(vec![])]);
+        assert!(parse(":nope").is_ok_and(|(rest, _)| rest == ":nope"));
+    }
+
+    #[test]
+    fn parse_strings() {
+        let string = |s: &str| Str(s.to_owned());
+        assert_parses_as("[hello world]p", &[string("hello world"), Print]);
+        assert_parses_as("[a[b]c][]", &[string("a[b]c"), string("")]);
+        assert_parses_as("[1 2 + # no comment]", &[string("1 2 + # no comment")]);
+        assert_parses_as(
+            "{:cat[!]}",
+            &[Fun(Box::new(Concat)), string("!"), Curry, Bind(vec![])],
+        );
+        assert!(parse("[a[b]").is_ok_and(|(rest, _)| rest == "[a[b]"));
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_parses_as(
+            "'(1 2.5 [a b] '())",
+            &[List(vec![
+                lit("1"),
+                lit("2.5"),
+                Str("a b".to_owned()),
+                List(vec![]),
+            ])],
+        );
+        assert_parses_as("'( 1/3r )p", &[List(vec![lit("1/3r")]), Print]);
+        assert_parses_as("(a)", &[Identifier("a".to_owned())]);
+        assert!(parse("'(1 +)").is_ok_and(|(rest, _)| rest == "'(1 +)"));
+    }
+
+    #[test]
+    fn parse_rationals() {
+        assert_parses_as("1/3r -10/4r", &[lit("1/3r"), lit("-10/4r")]);
+        assert_parses_as("1 /3r", &[lit("1"), Div, lit("3"), Repeat]);
+    }
+
+    #[test]
+    fn parse_complex_numbers() {
+        assert_parses_as(
+            "3+4j -2.5-1e3j 2j 1+2",
+            &[
+                lit("3+4j"),
+                lit("-2.5-1e3j"),
+                lit("2j"),
+                lit("1"),
+                Add,
+                lit("2"),
+            ],
+        );
+    }
+
     #[test]
     fn parse_identifiers() {
         assert_parses_as(
@@ -245,6 +577,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_global_identifiers() {
+        assert_parses_as(
+            "(::x)(y)",
+            &[Global("x".to_owned()), Identifier("y".to_owned())],
+        );
+        assert!(parse("(:x)").is_ok_and(|(rest, _)| rest == "(:x)"));
+    }
+
     #[test]
     fn partial_parsing() {
         assert_parses_as("\\++", &[Fun(Box::new(Add)), Add]);
@@ -253,8 +594,9 @@ mod tests {
 
     #[test]
     fn function_mode() {
-        assert_parses_as("{*2", &[Fun(Box::new(Mul)), Value(2.0), Curry]);
-        assert_parses_as("{+2}", &[Fun(Box::new(Add)), Value(2.0), Curry]);
+        assert_parses_as("{*2", &[Fun(Box::new(Mul)), lit("2"), Curry]);
+        assert_parses_as("{+2}", &[Fun(Box::new(Add)), lit("2"), Curry, Bind(vec![])]);
+        assert_parses_as("{}", &[Quotation(vec![]), Bind(vec![])]);
         assert_parses_as(
             "{?+@-@}",
             &[
@@ -265,7 +607,31 @@ mod tests {
                 Fun(Box::new(Sub)),
                 Curry,
                 Compose,
+                Bind(vec![]),
+            ],
+        );
+    }
+
+    #[test]
+    fn parameters() {
+        let id = |s: &str| Identifier(s.to_owned());
+        assert_parses_as(
+            "{a b -> (a) *2}",
+            &[
+                id("a"),
+                Fun(Box::new(Mul)),
+                lit("2"),
+                Curry,
+                Compose,
+                Bind(vec!["a".to_owned(), "b".to_owned()]),
             ],
         );
+        assert_parses_as("{x->}", &[Quotation(vec![]), Bind(vec!["x".to_owned()])]);
+        // Other identifiers still have to be composed manually
+        assert_parses_as(
+            "{x -> (x) (f) |}",
+            &[id("x"), id("f"), Compose, Bind(vec!["x".to_owned()])],
+        );
+        assert_parses_as("{d (x)}", &[Fun(Box::new(Dup)), id("x"), Bind(vec![])]);
     }
 }
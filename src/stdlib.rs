@@ -2,21 +2,41 @@ pub const STDLIB: &str = include_str!("stdlib.dcr");
 
 #[cfg(test)]
 mod tests {
-    use crate::{machine::Machine, parser::parse, V};
+    use crate::{dc::Dialect, machine::Machine, parser::parse, Mode, V};
+    use num_traits::ToPrimitive;
     use test_case::test_case;
 
-    fn expect_single_result(raw: &str) -> f64 {
+    fn run(raw: &str) -> Vec<V> {
         let input = parse(raw).expect("parsing failed").1;
-        let mut machine = Machine::new();
+        let mut machine = Machine::new(Mode::Decimal, Dialect::Dcr);
         for v in input {
-            machine.process(v).expect("processing failed");
+            machine
+                .process(v)
+                .unwrap_or_else(|e| panic!("processing failed: {e}"));
         }
-        match machine.stack.as_slice() {
-            [V::Value(f)] => *f,
+        machine.stack
+    }
+
+    fn expect_single_result(raw: &str) -> f64 {
+        match run(raw).as_slice() {
+            [V::Value(n)] => n.to_f64(),
+            [V::Rational(r)] => r.to_f64().unwrap(),
             s => panic!("stack should be a single value but was {s:?}"),
         }
     }
 
+    /// The numbers on the stack, with lists unpacked.
+    fn expect_numbers(raw: &str) -> Vec<f64> {
+        fn numbers(v: &V) -> Vec<f64> {
+            match v {
+                V::Value(n) => vec![n.to_f64()],
+                V::List(items) => items.iter().flat_map(numbers).collect(),
+                v => panic!("expected a number or a list but got {v:?}"),
+            }
+        }
+        run(raw).iter().flat_map(numbers).collect()
+    }
+
     #[test_case(2.0, 4.0 => 2.0)]
     #[test_case(4.0, 2.0 => 2.0)]
     #[test_case(4.0, 4.0 => 4.0)]
@@ -44,5 +64,46 @@ mod tests {
     fn average() {
         assert_eq!(expect_single_result("1 2 3 4 5 6 7 8 (avg)$"), 4.5);
         assert_eq!(expect_single_result("1 -1 2 -2 0 0 (avg)$"), 0.0);
+        assert_eq!(expect_single_result("7 (avg)$"), 7.0);
+    }
+
+    #[test_case("'(1 2 3 4) (listSum)$" => 10.0)]
+    #[test_case("'() (listSum)$" => 0.0)]
+    #[test_case("5 '(1 2) (listSum)$ +" => 8.0; "the rest of the stack is untouched")]
+    #[test_case("'(1 2 3 4) (listAvg)$" => 2.5)]
+    #[test_case("'(-3) (listAvg)$" => -3.0)]
+    fn lists(input: &str) -> f64 {
+        expect_single_result(input)
+    }
+
+    #[test_case("1 10 2 (range)$" => vec![1.0, 3.0, 5.0, 7.0, 9.0])]
+    #[test_case("0 1 0.25 (range)$" => vec![0.0, 0.25, 0.5, 0.75])]
+    #[test_case("3 0 -1 (range)$" => vec![3.0, 2.0, 1.0])]
+    #[test_case("5 0 1 (range)$ S" => vec![0.0]; "empty range")]
+    #[test_case("4 (iota)$" => vec![0.0, 1.0, 2.0, 3.0])]
+    #[test_case("0 (iota)$ :len" => vec![0.0])]
+    #[test_case("0 1 5 :linspace" => vec![0.0, 0.25, 0.5, 0.75, 1.0])]
+    #[test_case("2 -2 3 :linspace" => vec![2.0, 0.0, -2.0])]
+    #[test_case("0 1 3 :linspace 2 :get" => vec![1.0]; "the end is exact")]
+    #[test_case("7 9 1 :linspace 7 9 0 :linspace :len" => vec![7.0, 0.0])]
+    fn sequences(input: &str) -> Vec<f64> {
+        expect_numbers(input)
+    }
+
+    #[test_case("1 5 0 (range)$" => panics "in steps of 0")]
+    #[test_case("0 1 -1 :linspace" => panics "Cannot make a list of -1 elements")]
+    #[test_case("10 :maxiter 0 100 1 (range)$" => panics "more than 10 elements")]
+    #[test_case("0 1 100000000000 :linspace" => panics "more than the limit of 1000000")]
+    #[test_case("10 :maxiter 0 1 11 :linspace" => panics "more than the limit of 10")]
+    fn invalid_sequences(input: &str) {
+        run(input);
+    }
+
+    #[test_case("(min)")]
+    #[test_case("(max)")]
+    #[test_case("(avg)")]
+    fn registers_are_untouched(function: &str) {
+        let result = expect_single_result(&format!("5 256s 6 257s 2 4 {function}$ c 256l 257l +"));
+        assert_eq!(result, 11.0);
     }
 }
@@ -0,0 +1,37 @@
+//! Runs the programs in `tests/dc` with `dcr --dc` and compares their output to what GNU `dc` prints.
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+use test_case::test_case;
+
+#[test_case("arithmetic")]
+#[test_case("conditionals")]
+#[test_case("loops")]
+#[test_case("numbers")]
+#[test_case("printing")]
+#[test_case("radix")]
+#[test_case("scale")]
+#[test_case("stack")]
+fn conformance(name: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dc");
+    let program = fs::read_to_string(dir.join(name).with_extension("dc")).unwrap();
+    let expected = fs::read_to_string(dir.join(name).with_extension("out")).unwrap();
+    let mut dcr = Command::new(env!("CARGO_BIN_EXE_dcr"))
+        .arg("--dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    dcr.stdin
+        .take()
+        .unwrap()
+        .write_all(program.as_bytes())
+        .unwrap();
+    let output = dcr.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}